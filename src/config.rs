@@ -1,16 +1,17 @@
 use std::env;
+use crate::error::LexError;
 
 pub struct Config {
     pub filename: String,
 }
 
 impl Config {
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
+    pub fn new(mut args: env::Args) -> Result<Config, LexError> {
         args.next();
 
         // Get filename from arguments
         let filename = match args.next() {
-            None => return Err("Didn't get a filename"),
+            None => return Err(LexError::MissingFilename),
             Some(arg) => arg
         };
 
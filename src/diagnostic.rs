@@ -0,0 +1,199 @@
+use std::fmt::Write;
+use crate::error::{LexError, LexWarning};
+use crate::span::{Span, TAB_SIZE};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn colour(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+// A message about the source with the location it points at and other related locations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub labels: Vec<(Span, String)>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message, span, labels: Vec::new(), help: None }
+    }
+
+    pub fn warning(message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, message, span, labels: Vec::new(), help: None }
+    }
+
+    pub fn with_label(mut self, span: Span, label: String) -> Diagnostic {
+        self.labels.push((span, label));
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Diagnostic {
+        self.help = Some(help);
+        self
+    }
+}
+
+impl From<&LexError> for Diagnostic {
+    fn from(err: &LexError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(err.message(), err.span());
+
+        if let Some((span, label)) = err.related() {
+            diagnostic = diagnostic.with_label(span, label);
+        }
+
+        match err.help() {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&LexWarning> for Diagnostic {
+    fn from(warning: &LexWarning) -> Diagnostic {
+        Diagnostic::warning(warning.message(), Some(warning.span()))
+    }
+}
+
+/*
+ * Render a diagnostic like rustc does: the message, the file with the line and column, the line
+ * of source and a caret under the span. Related locations follow with their label. Lines and
+ * columns are shown starting at 1.
+ *
+ *   error: Closing ']' does not match opening '(' on line 3
+ *    --> main.py:3:10
+ *     |
+ *   3 | x = (1, 2]
+ *     |          ^
+ *    ::: main.py:3:5
+ *     |
+ *   3 | x = (1, 2]
+ *     |     - opening '(' is here
+ */
+pub fn render(diagnostic: &Diagnostic, filename: &str, source: &str, colour: bool) -> String {
+    let paint = |code: &'static str| if colour { code } else { "" };
+    let mut output = String::new();
+
+    let _ = writeln!(
+        output, "{}{}{}: {}{}{}",
+        paint(diagnostic.severity.colour()), diagnostic.severity.name(), paint(RESET),
+        paint(BOLD), diagnostic.message, paint(RESET)
+    );
+
+    let span = match diagnostic.span {
+        Some(span) => span,
+        None => {
+            let _ = writeln!(output, " {}-->{} {}", paint(BLUE), paint(RESET), filename);
+            if let Some(help) = &diagnostic.help {
+                let _ = writeln!(output, " {}={} help: {}", paint(BLUE), paint(RESET), help);
+            }
+            return output;
+        }
+    };
+
+    // Every line number shown fits in the gutter
+    let width = diagnostic.labels.iter()
+        .map(|(span, _)| span.start.line)
+        .chain(std::iter::once(span.start.line))
+        .max()
+        .unwrap_or(0)
+        .to_string()
+        .len();
+    let gutter = " ".repeat(width);
+
+    let _ = writeln!(
+        output, "{}{}-->{} {}:{}:{}",
+        gutter, paint(BLUE), paint(RESET), filename, span.start.line, span.start.column + 1
+    );
+    snippet(&mut output, source, span, width, ('^', paint(diagnostic.severity.colour())), "", colour);
+
+    for (span, label) in &diagnostic.labels {
+        let _ = writeln!(
+            output, "{}{}:::{} {}:{}:{}",
+            gutter, paint(BLUE), paint(RESET), filename, span.start.line, span.start.column + 1
+        );
+        snippet(&mut output, source, *span, width, ('-', paint(BLUE)), label, colour);
+    }
+
+    if let Some(help) = &diagnostic.help {
+        let _ = writeln!(output, "{} {}={} help: {}", gutter, paint(BLUE), paint(RESET), help);
+    }
+
+    output
+}
+
+// Line of source of the span with a mark under it
+fn snippet(output: &mut String, source: &str, span: Span, width: usize, mark: (char, &str), label: &str, colour: bool) {
+    let paint = |code: &'static str| if colour { code } else { "" };
+    let gutter = " ".repeat(width);
+    let line = expand_tabs(source_line(source, span.start.line));
+
+    // Columns have the tabs expanded like the line shown
+    let padding = " ".repeat(span.start.column);
+    let length = if span.end.line == span.start.line {
+        span.end.column.saturating_sub(span.start.column)
+    } else {
+        line.chars().count().saturating_sub(span.start.column)
+    };
+    let underline = mark.0.to_string().repeat(length.max(1));
+    let label = if label.is_empty() { String::new() } else { format!(" {}", label) };
+
+    let _ = writeln!(output, "{} {}|{}", gutter, paint(BLUE), paint(RESET));
+    let _ = writeln!(output, "{}{:>width$} |{} {}", paint(BLUE), span.start.line, paint(RESET), line, width = width);
+    let _ = writeln!(
+        output, "{} {}|{} {}{}{}{}{}",
+        gutter, paint(BLUE), paint(RESET), padding, mark.1, underline, label, paint(RESET)
+    );
+}
+
+// Line of the source, ended by any of the newline conventions
+fn source_line(source: &str, number: usize) -> &str {
+    source.split('\n')
+        .flat_map(|line| {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            line.split('\r')
+        })
+        .nth(number.saturating_sub(1))
+        .unwrap_or("")
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::new();
+
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = TAB_SIZE - expanded.chars().count() % TAB_SIZE;
+            expanded.push_str(&" ".repeat(spaces));
+        } else {
+            expanded.push(c);
+        }
+    }
+
+    expanded
+}
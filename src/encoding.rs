@@ -0,0 +1,137 @@
+use crate::span::{Position, Span};
+
+pub const BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+// Characters of cp1252 from 0x80 to 0x9F, the rest of the bytes are the same as in latin-1
+const CP1252: [Option<char>; 32] = [
+    Some('€'), None, Some('‚'), Some('ƒ'), Some('„'), Some('…'), Some('†'), Some('‡'),
+    Some('ˆ'), Some('‰'), Some('Š'), Some('‹'), Some('Œ'), None, Some('Ž'), None,
+    None, Some('‘'), Some('’'), Some('“'), Some('”'), Some('•'), Some('–'), Some('—'),
+    Some('˜'), Some('™'), Some('š'), Some('›'), Some('œ'), None, Some('ž'), Some('Ÿ'),
+];
+
+// Encodings the source can be declared in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Cp1252,
+}
+
+impl Encoding {
+    // Encoding for a name of a coding declaration, with the aliases CPython accepts for them
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        let name = name.to_lowercase().replace('_', "-");
+        let is = |encoding: &str| (name == encoding) | name.starts_with(&format!("{}-", encoding));
+
+        if is("utf-8") | is("utf8") {
+            Some(Encoding::Utf8)
+        } else if is("latin-1") | is("latin1") | is("iso-8859-1") | is("iso8859-1") | is("iso-latin-1") | (name == "l1") {
+            Some(Encoding::Latin1)
+        } else if (name == "cp1252") | (name == "windows-1252") {
+            Some(Encoding::Cp1252)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin-1",
+            Encoding::Cp1252 => "cp1252",
+        }
+    }
+
+    // Character of a byte in the single byte encodings
+    pub fn decode_byte(&self, byte: u8) -> Option<char> {
+        match self {
+            Encoding::Utf8 if byte.is_ascii() => Some(byte as char),
+            Encoding::Utf8 => None,
+            Encoding::Latin1 => Some(byte as char),
+            Encoding::Cp1252 => match byte {
+                0x80..=0x9F => CP1252[(byte - 0x80) as usize],
+                _ => Some(byte as char),
+            },
+        }
+    }
+
+    // Decode bytes already validated, anything else is replaced
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            _ => bytes.iter().map(|byte| self.decode_byte(*byte).unwrap_or('\u{fffd}')).collect(),
+        }
+    }
+
+    // Encode text decoded from this encoding, characters it doesn't have are written as '?'
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Latin1 => text.chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect(),
+            Encoding::Cp1252 => text.chars()
+                .map(|c| match CP1252.iter().position(|special| *special == Some(c)) {
+                    Some(index) => 0x80 + index as u8,
+                    None => match u8::try_from(c) {
+                        Ok(byte) if !(0x80..=0x9F).contains(&byte) => byte,
+                        _ => b'?',
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+/*
+ * Find the coding declaration of PEP 263, a comment matching `coding[:=]\s*([-\w.]+)` on the
+ * first line, or on the second one when the first is blank or a comment. Returns the name and
+ * the span of the comment line.
+ */
+pub fn coding_cookie(bytes: &[u8], start: Position) -> Option<(String, Span)> {
+    let mut offset = 0;
+
+    for line_number in 1..=2 {
+        let end = match bytes[offset..].iter().position(|byte| (*byte == b'\n') | (*byte == b'\r')) {
+            Some(index) => offset + index,
+            None => bytes.len(),
+        };
+
+        let line = String::from_utf8_lossy(&bytes[offset..end]);
+        let comment = line.trim_start_matches([' ', '\t', '\x0c']);
+
+        if comment.starts_with('#') {
+            for (index, _) in comment.match_indices("coding") {
+                let rest = &comment[index + "coding".len()..];
+                if !rest.starts_with([':', '=']) {
+                    continue;
+                }
+
+                let name: String = rest[1..].trim_start_matches([' ', '\t'])
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() | (*c == '-') | (*c == '_') | (*c == '.'))
+                    .collect();
+
+                if !name.is_empty() {
+                    let line_start = Position { offset: start.offset + offset, line: line_number, column: 0 };
+                    let mut line_end = line_start;
+                    line_end.advance_str(&line);
+                    line_end.offset = start.offset + end;
+
+                    return Some((name, Span::new(line_start, line_end)));
+                }
+            }
+        } else if !comment.trim_end().is_empty() {
+            // Code on the first line, the declaration can't be on the second one
+            return None;
+        }
+
+        if end == bytes.len() {
+            return None;
+        }
+        offset = if bytes[end..].starts_with(b"\r\n") { end + 2 } else { end + 1 };
+    }
+
+    None
+}
@@ -0,0 +1,211 @@
+use std::{error, fmt};
+use crate::span::Span;
+
+// Errors found while reading the source, each one carries where it happened and what was read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    MissingFilename,
+    Io { message: String, span: Option<Span> },
+    InvalidUtf8 { byte: u8, span: Span },
+    UndecodableByte { byte: u8, encoding: &'static str, span: Span },
+    NullByte { span: Span },
+    UnknownEncoding { name: String, span: Span },
+    EncodingConflict { name: String, span: Span },
+    IllegalCharacter { span: Span, text: String },
+    UnexpectedCharacterAfterContinuation { span: Span, text: String },
+    UnexpectedEofAfterContinuation { span: Span, text: String },
+    InconsistentDedent { span: Span, text: String },
+    InconsistentTabs { span: Span, text: String, block: String },
+    UnterminatedString { span: Span, text: String },
+    UnterminatedTripleQuotedString { span: Span, text: String },
+    InvalidString { message: String, span: Span, text: String },
+    UnterminatedFString { span: Span, text: String },
+    FStringSingleBrace { span: Span, text: String },
+    FStringExpectingBrace { span: Span, text: String },
+    InvalidNumber { radix: &'static str, span: Span, text: String },
+    InvalidDigit { digit: char, radix: &'static str, span: Span, text: String },
+    LeadingZeros { span: Span, text: String },
+    MissingExponent { span: Span, text: String },
+    UnmatchedBracket { span: Span, text: String },
+    MismatchedBracket { span: Span, text: String, opening: char, opening_span: Span },
+    UnclosedBracket { span: Span, text: String },
+}
+
+impl LexError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LexError::MissingFilename => None,
+            LexError::Io { span, .. } => *span,
+            LexError::InvalidUtf8 { span, .. } |
+            LexError::UndecodableByte { span, .. } |
+            LexError::NullByte { span } |
+            LexError::UnknownEncoding { span, .. } |
+            LexError::EncodingConflict { span, .. } |
+            LexError::IllegalCharacter { span, .. } |
+            LexError::UnexpectedCharacterAfterContinuation { span, .. } |
+            LexError::UnexpectedEofAfterContinuation { span, .. } |
+            LexError::InconsistentDedent { span, .. } |
+            LexError::InconsistentTabs { span, .. } |
+            LexError::UnterminatedString { span, .. } |
+            LexError::UnterminatedTripleQuotedString { span, .. } |
+            LexError::InvalidString { span, .. } |
+            LexError::UnterminatedFString { span, .. } |
+            LexError::FStringSingleBrace { span, .. } |
+            LexError::FStringExpectingBrace { span, .. } |
+            LexError::InvalidNumber { span, .. } |
+            LexError::InvalidDigit { span, .. } |
+            LexError::LeadingZeros { span, .. } |
+            LexError::MissingExponent { span, .. } |
+            LexError::UnmatchedBracket { span, .. } |
+            LexError::MismatchedBracket { span, .. } |
+            LexError::UnclosedBracket { span, .. } => Some(*span),
+        }
+    }
+
+    // Source text the error points at
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            LexError::MissingFilename |
+            LexError::Io { .. } |
+            LexError::InvalidUtf8 { .. } |
+            LexError::UndecodableByte { .. } |
+            LexError::NullByte { .. } => None,
+            LexError::UnknownEncoding { name, .. } |
+            LexError::EncodingConflict { name, .. } => Some(name),
+            LexError::IllegalCharacter { text, .. } |
+            LexError::UnexpectedCharacterAfterContinuation { text, .. } |
+            LexError::UnexpectedEofAfterContinuation { text, .. } |
+            LexError::InconsistentDedent { text, .. } |
+            LexError::InconsistentTabs { text, .. } |
+            LexError::UnterminatedString { text, .. } |
+            LexError::UnterminatedTripleQuotedString { text, .. } |
+            LexError::InvalidString { text, .. } |
+            LexError::UnterminatedFString { text, .. } |
+            LexError::FStringSingleBrace { text, .. } |
+            LexError::FStringExpectingBrace { text, .. } |
+            LexError::InvalidNumber { text, .. } |
+            LexError::InvalidDigit { text, .. } |
+            LexError::LeadingZeros { text, .. } |
+            LexError::MissingExponent { text, .. } |
+            LexError::UnmatchedBracket { text, .. } |
+            LexError::MismatchedBracket { text, .. } |
+            LexError::UnclosedBracket { text, .. } => Some(text),
+        }
+    }
+
+    // Description of the error without its location
+    pub fn message(&self) -> String {
+        match self {
+            LexError::MissingFilename => String::from("Didn't get a filename"),
+            LexError::Io { message, .. } => format!("Couldn't read the file: {}", message),
+            LexError::InvalidUtf8 { byte, .. } => format!("Invalid UTF-8 byte 0x{:02x} in the file", byte),
+            LexError::UndecodableByte { byte, encoding, .. } => format!("Invalid {} byte 0x{:02x} in the file", encoding, byte),
+            LexError::NullByte { .. } => String::from("Source code cannot contain null bytes"),
+            LexError::UnknownEncoding { name, .. } => format!("Unknown encoding: {}", name),
+            LexError::EncodingConflict { name, .. } => format!("Encoding problem: {} with BOM", name),
+            LexError::IllegalCharacter { text, .. } => format!("Invalid character '{}'", text),
+            LexError::UnexpectedCharacterAfterContinuation { .. } =>
+                String::from("Unexpected character after line continuation character"),
+            LexError::UnexpectedEofAfterContinuation { .. } =>
+                String::from("Unexpected EOF after line continuation character"),
+            LexError::InconsistentDedent { .. } =>
+                String::from("Unindent does not match any outer indentation level"),
+            LexError::InconsistentTabs { .. } => String::from("Inconsistent use of tabs and spaces in indentation"),
+            LexError::UnterminatedString { .. } => String::from("Unterminated string literal"),
+            LexError::UnterminatedTripleQuotedString { .. } => String::from("Unterminated triple-quoted string literal"),
+            LexError::InvalidString { message, .. } => message.clone(),
+            LexError::UnterminatedFString { .. } => String::from("Unterminated f-string literal"),
+            LexError::FStringSingleBrace { .. } => String::from("f-string: single '}' is not allowed"),
+            LexError::FStringExpectingBrace { .. } => String::from("f-string: expecting '}'"),
+            LexError::InvalidNumber { radix, .. } => format!("Invalid {} literal", radix),
+            LexError::InvalidDigit { digit, radix, .. } => format!("Invalid digit '{}' in {} literal", digit, radix),
+            LexError::LeadingZeros { .. } => String::from("Leading zeros in decimal integer literals are not permitted"),
+            LexError::MissingExponent { .. } => String::from("Expected digits in the exponent of the decimal literal"),
+            LexError::UnmatchedBracket { text, .. } => format!("Unmatched '{}'", text),
+            LexError::MismatchedBracket { text, opening, opening_span, .. } => format!(
+                "Closing '{}' does not match opening '{}' on line {}", text, opening, opening_span.start.line
+            ),
+            LexError::UnclosedBracket { text, .. } => format!("'{}' was never closed", text),
+        }
+    }
+
+    // Other location of the source involved in the error, with a label for it
+    pub fn related(&self) -> Option<(Span, String)> {
+        match self {
+            LexError::MismatchedBracket { opening, opening_span, .. } =>
+                Some((*opening_span, format!("opening '{}' is here", opening))),
+            _ => None,
+        }
+    }
+
+    // Suggestion shown below the source in diagnostics
+    pub fn help(&self) -> Option<String> {
+        let help = match self {
+            LexError::InconsistentTabs { text, block, .. } => return Some(format!(
+                "the line is indented with {:?} and the block with {:?}, use only spaces or only tabs", text, block
+            )),
+            LexError::InvalidUtf8 { .. } => "declare the encoding of the file with a coding comment on its first line",
+            LexError::UnknownEncoding { .. } => "the supported encodings are utf-8, latin-1 and cp1252",
+            LexError::EncodingConflict { .. } => "a file starting with a UTF-8 byte order mark must be encoded in utf-8",
+            LexError::UnexpectedCharacterAfterContinuation { .. } =>
+                "a backslash only joins lines when it is the last character of the line",
+            LexError::InconsistentDedent { .. } => "indent the line like one of the enclosing blocks",
+            LexError::FStringSingleBrace { .. } => "a literal '}' is written as '}}'",
+            LexError::LeadingZeros { .. } => "use an 0o prefix for octal integers",
+            _ => return None,
+        };
+
+        Some(String::from(help))
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span() {
+            // The message already says where both brackets are
+            Some(_) if self.related().is_some() => write!(f, "{}", self.message()),
+            Some(span) => write!(f, "{} on line {}", self.message(), span.start.line),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl error::Error for LexError {}
+
+// Problems in the source that don't stop the analysis, each one carries the token it was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexWarning {
+    InvalidEscapeSequence { message: String, span: Span, text: String },
+    InvalidNumber { radix: &'static str, span: Span, text: String },
+}
+
+impl LexWarning {
+    pub fn span(&self) -> Span {
+        match self {
+            LexWarning::InvalidEscapeSequence { span, .. } |
+            LexWarning::InvalidNumber { span, .. } => *span,
+        }
+    }
+
+    // Source text the warning points at
+    pub fn text(&self) -> &str {
+        match self {
+            LexWarning::InvalidEscapeSequence { text, .. } |
+            LexWarning::InvalidNumber { text, .. } => text,
+        }
+    }
+
+    // Description of the warning without its location
+    pub fn message(&self) -> String {
+        match self {
+            LexWarning::InvalidEscapeSequence { message, .. } => message.clone(),
+            LexWarning::InvalidNumber { radix, .. } => format!("Invalid {} literal", radix),
+        }
+    }
+}
+
+impl fmt::Display for LexWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} on line {}", self.message(), self.span().start.line)
+    }
+}
@@ -0,0 +1,234 @@
+use crate::token::{StringPrefix, StringValue};
+
+// Subset of the Unicode character names accepted by \N{...}
+const CHARACTER_NAMES: [(&str, char); 32] = [
+    ("NULL", '\u{0}'),
+    ("CHARACTER TABULATION", '\t'),
+    ("LINE FEED", '\n'),
+    ("CARRIAGE RETURN", '\r'),
+    ("SPACE", ' '),
+    ("QUOTATION MARK", '"'),
+    ("APOSTROPHE", '\''),
+    ("REVERSE SOLIDUS", '\\'),
+    ("NO-BREAK SPACE", '\u{a0}'),
+    ("SECTION SIGN", '§'),
+    ("COPYRIGHT SIGN", '©'),
+    ("REGISTERED SIGN", '®'),
+    ("DEGREE SIGN", '°'),
+    ("PLUS-MINUS SIGN", '±'),
+    ("MICRO SIGN", 'µ'),
+    ("MULTIPLICATION SIGN", '×'),
+    ("DIVISION SIGN", '÷'),
+    ("LATIN SMALL LETTER A WITH GRAVE", 'à'),
+    ("LATIN SMALL LETTER E WITH ACUTE", 'é'),
+    ("LATIN SMALL LETTER N WITH TILDE", 'ñ'),
+    ("LATIN SMALL LETTER U WITH DIAERESIS", 'ü'),
+    ("LATIN CAPITAL LETTER E WITH ACUTE", 'É'),
+    ("GREEK SMALL LETTER ALPHA", 'α'),
+    ("GREEK SMALL LETTER PI", 'π'),
+    ("EN DASH", '–'),
+    ("EM DASH", '—'),
+    ("LEFT DOUBLE QUOTATION MARK", '“'),
+    ("RIGHT DOUBLE QUOTATION MARK", '”'),
+    ("BULLET", '•'),
+    ("HORIZONTAL ELLIPSIS", '…'),
+    ("EURO SIGN", '€'),
+    ("CHECK MARK", '✓'),
+];
+
+/*
+ * Decode the value of a string literal from its lexeme. Invalid escape sequences are kept as they
+ * are and reported in the returned warnings, like CPython's SyntaxWarning. Malformed \x, \u, \U
+ * and \N escapes are errors.
+ */
+pub fn decode(lexeme: &str, prefix: StringPrefix) -> Result<(StringValue, Vec<String>), String> {
+    // Strip the prefix and the quotes
+    let literal = lexeme.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let quotes = if literal.len() >= 6 && (literal.starts_with("'''") || literal.starts_with("\"\"\"")) {
+        3
+    } else {
+        1
+    };
+    // The newlines of the source are read as '\n' whatever their convention
+    let body = literal[quotes..literal.len() - quotes].replace("\r\n", "\n").replace('\r', "\n");
+
+    if prefix.is_bytes() && !body.is_ascii() {
+        return Err(String::from("bytes can only contain ASCII literal characters"));
+    }
+
+    if prefix.is_raw() {
+        let value = if prefix.is_bytes() {
+            StringValue::Bytes(body.bytes().collect())
+        } else {
+            StringValue::Text(body.to_string())
+        };
+        return Ok((value, Vec::new()));
+    }
+
+    let mut warnings = Vec::new();
+    let mut value: Vec<u32> = Vec::new();
+    let mut chars = body.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c as u32);
+            continue;
+        }
+
+        let escape = match chars.next() {
+            Some(escape) => escape,
+            None => {
+                value.push('\\' as u32);
+                break;
+            }
+        };
+
+        match escape {
+            '\n' => (),
+            '\\' | '\'' | '"' => value.push(escape as u32),
+            'a' => value.push(0x07),
+            'b' => value.push(0x08),
+            'f' => value.push(0x0c),
+            'n' => value.push('\n' as u32),
+            'r' => value.push('\r' as u32),
+            't' => value.push('\t' as u32),
+            'v' => value.push(0x0b),
+            '0'..='7' => {
+                // Up to three octal digits
+                let mut code = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+
+                if code > 0o377 {
+                    warnings.push(format!("invalid octal escape sequence '\\{:o}'", code));
+                    if prefix.is_bytes() {
+                        code &= 0xff;
+                    }
+                }
+                value.push(code);
+            }
+            'x' => value.push(hexadecimal(&mut chars, 2, "\\xXX")?),
+            'u' if !prefix.is_bytes() => value.push(hexadecimal(&mut chars, 4, "\\uXXXX")?),
+            'U' if !prefix.is_bytes() => {
+                let code = hexadecimal(&mut chars, 8, "\\UXXXXXXXX")?;
+                if char::from_u32(code).is_none() {
+                    return Err(String::from("illegal Unicode character"));
+                }
+                value.push(code);
+            }
+            'N' if !prefix.is_bytes() => {
+                if chars.next() != Some('{') {
+                    return Err(String::from("malformed \\N character escape"));
+                }
+
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(String::from("malformed \\N character escape")),
+                    }
+                }
+
+                match CHARACTER_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+                    Some((_, c)) => value.push(*c as u32),
+                    None => return Err(format!("unknown Unicode character name '{}'", name)),
+                }
+            }
+            _ => {
+                warnings.push(format!("invalid escape sequence '\\{}'", escape));
+                value.push('\\' as u32);
+                value.push(escape as u32);
+            }
+        }
+    }
+
+    let value = if prefix.is_bytes() {
+        StringValue::Bytes(value.into_iter().map(|code| code as u8).collect())
+    } else {
+        // Lone surrogates from \uXXXX can't be represented, they are replaced
+        StringValue::Text(value.into_iter().map(|code| char::from_u32(code).unwrap_or('\u{fffd}')).collect())
+    };
+
+    Ok((value, warnings))
+}
+
+// Read exactly n hexadecimal digits of an escape sequence
+fn hexadecimal(chars: &mut impl Iterator<Item = char>, n: usize, escape: &str) -> Result<u32, String> {
+    let mut code = 0;
+
+    for _ in 0..n {
+        match chars.next().and_then(|c| c.to_digit(16)) {
+            Some(digit) => code = code * 16 + digit,
+            None => return Err(format!("truncated {} escape", escape)),
+        }
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Value of a literal that isn't bytes
+    fn text(value: &str) -> StringValue {
+        StringValue::Text(String::from(value))
+    }
+
+    #[test]
+    fn hexadecimal_escape() {
+        assert_eq!(decode(r"'\x41'", StringPrefix::None), Ok((text("A"), Vec::new())));
+        assert_eq!(decode(r"b'\x41\xff'", StringPrefix::Bytes), Ok((StringValue::Bytes(vec![0x41, 0xff]), Vec::new())));
+    }
+
+    #[test]
+    fn octal_escape() {
+        assert_eq!(decode(r"'\101\0\12'", StringPrefix::None), Ok((text("A\0\n"), Vec::new())));
+
+        // Past \377 in bytes only the low byte is kept, with a warning
+        let (value, warnings) = decode(r"b'\777'", StringPrefix::Bytes).unwrap();
+        assert_eq!(value, StringValue::Bytes(vec![0xff]));
+        assert_eq!(warnings, vec![String::from(r"invalid octal escape sequence '\777'")]);
+    }
+
+    #[test]
+    fn named_escape() {
+        assert_eq!(decode(r"'\N{EURO SIGN}'", StringPrefix::None), Ok((text("€"), Vec::new())));
+        assert_eq!(decode(r"'\N{euro sign}'", StringPrefix::None), Ok((text("€"), Vec::new())));
+        assert_eq!(
+            decode(r"'\N{NOT A CHARACTER}'", StringPrefix::None),
+            Err(String::from("unknown Unicode character name 'NOT A CHARACTER'"))
+        );
+        assert_eq!(decode(r"'\N{EURO SIGN'", StringPrefix::None), Err(String::from(r"malformed \N character escape")));
+    }
+
+    #[test]
+    fn unicode_escape_in_bytes() {
+        // \u isn't an escape in bytes, it's kept with a warning
+        let (value, warnings) = decode(r"b'\u0041'", StringPrefix::Bytes).unwrap();
+        assert_eq!(value, StringValue::Bytes(b"\\u0041".to_vec()));
+        assert_eq!(warnings, vec![String::from(r"invalid escape sequence '\u'")]);
+    }
+
+    #[test]
+    fn truncated_escapes() {
+        assert_eq!(decode(r"'\x4'", StringPrefix::None), Err(String::from(r"truncated \xXX escape")));
+        assert_eq!(decode(r"'\u004'", StringPrefix::None), Err(String::from(r"truncated \uXXXX escape")));
+        assert_eq!(decode(r"b'\x'", StringPrefix::Bytes), Err(String::from(r"truncated \xXX escape")));
+    }
+
+    #[test]
+    fn escaped_newline() {
+        // A backslash at the end of a line joins it to the next one, whatever its newline
+        assert_eq!(decode("'''a\\\nb'''", StringPrefix::None), Ok((text("ab"), Vec::new())));
+        assert_eq!(decode("'a\\\r\nb'", StringPrefix::None), Ok((text("ab"), Vec::new())));
+    }
+}
@@ -1,137 +1,720 @@
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::Read;
-use crate::Config;
+use std::io::{self, Cursor, Read, Stdin};
+use std::path::Path;
+use crate::encoding::{self, Encoding, BOM};
+use crate::error::LexError;
+use crate::span::{Position, Span};
 
+#[cfg(not(test))]
 const BUFFER_SIZE: usize = 100;
-const BUFFER_A_EOF: usize = BUFFER_SIZE - 1;
-const BUFFER_B_EOF: usize = (2 * BUFFER_SIZE) - 1;
 
-pub struct DoubleBuffer {
-    file: File,
+// Buffers of a few bytes in the tests, so the lexemes cross many buffer boundaries
+#[cfg(test)]
+const BUFFER_SIZE: usize = 8;
+
+// Place of the forward pointer in the source, the end of file counting as one more byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Mark(pub(crate) usize);
+
+/*
+ * Source the lexical analyzer reads one character at a time. The characters read since the begin
+ * pointer are the lexeme, and the forward pointer can go back over them to read them again.
+ * Lexemes can be borrowed for the lifetime of the source when the input system holds it.
+ */
+pub trait InputSystem<'a>: Iterator<Item = Result<char, LexError>> {
+    // Encoding the source is read in
+    fn encoding(&self) -> Encoding;
+
+    // The source starts with a UTF-8 byte order mark, skipped before the first lexeme
+    fn bom(&self) -> bool;
+
+    // Take the lexeme read, the next one starts after it
+    fn get_lexeme(&mut self) -> Cow<'a, str>;
+
+    // Text read since the start of the lexeme and the position after it, without moving the begin pointer
+    fn peek_lexeme(&self) -> (Cow<'a, str>, Position);
+
+    // Skip the lexeme read
+    fn reject(&mut self);
+
+    // Position of the begin pointer, where the next lexeme starts
+    fn position(&self) -> Position;
+
+    // Go back over the last character read
+    fn back(&mut self);
+
+    // Remember where the forward pointer is, to go back to it with reset
+    fn mark(&self) -> Mark;
+
+    /*
+     * Go back to a mark to read again the characters after it. The characters of the lexeme stay
+     * in the input until it's taken, so any mark made since the start of the lexeme can be reset.
+     * A mark before the start of the lexeme can't be reset and panics.
+     */
+    fn reset(&mut self, mark: Mark);
+
+    // Character n places ahead of the forward pointer without reading it, peek(0) being the next one
+    fn peek(&mut self, n: usize) -> Option<Result<char, LexError>> {
+        let mark = self.mark();
+        let c = self.nth(n);
+        self.reset(mark);
+
+        c
+    }
+}
+
+/*
+ * Bytes of the source the characters are decoded from. The input systems share the decoding of
+ * the characters and the way back over them, written on top of these.
+ */
+pub(crate) trait ByteInput {
+    // Read the next byte, None once the end of the file is read
+    fn next_byte(&mut self) -> Option<Result<u8, LexError>>;
+
+    // Go back over the last byte read, or over the end of the file
+    fn back_byte(&mut self);
+
+    // Last byte read of the lexeme, the one back_byte goes back over
+    fn last_byte(&self) -> Option<u8>;
+
+    // The end of the file was read
+    fn past_end(&self) -> bool;
+
+    // Error for the character of the last bytes read
+    fn invalid_byte(&self, length: usize) -> LexError;
+}
+
+// Buffered input of the source, read from a file by default but from anything implementing Read
+pub struct DoubleBuffer<R = File> {
+    reader: R,
+    // First lines of the source, read to find the coding declaration and loaded before the reader
+    head: Cursor<Vec<u8>>,
     buffer: [u8; 2 * BUFFER_SIZE],
+    // Bytes read in each buffer, a buffer that isn't full ends the file
+    lengths: [usize; 2],
+    // Start of a lexeme read across a buffer boundary, saved before its buffer is loaded again
+    spill: Vec<u8>,
+    // Bytes of the spill buffer gone back over, read again before the buffers, the next one last
+    replay: Vec<u8>,
     begin: usize,
     forward: isize,
+    reload: bool,
+    position: Position,
+    encoding: Encoding,
+    bom: bool,
 }
 
-impl DoubleBuffer {
-    pub fn new(config: Config) -> Result<DoubleBuffer, &'static str> {
-        let begin = 0;
-        let forward = -1;
+impl DoubleBuffer<File> {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<DoubleBuffer<File>, LexError> {
+        match File::open(path) {
+            Ok(file) => DoubleBuffer::new(file),
+            Err(err) => Err(LexError::Io { message: err.to_string(), span: None })
+        }
+    }
+}
 
-        let mut file = match File::open(config.filename) {
-            Ok(file) => file,
-            Err(_) => return Err("Couldn't read the file")
+impl<'a> DoubleBuffer<&'a [u8]> {
+    pub fn from_text(text: &'a str) -> Result<DoubleBuffer<&'a [u8]>, LexError> {
+        DoubleBuffer::new(text.as_bytes())
+    }
+}
+
+impl DoubleBuffer<Stdin> {
+    pub fn stdin() -> Result<DoubleBuffer<Stdin>, LexError> {
+        DoubleBuffer::new(io::stdin())
+    }
+}
+
+impl<R: Read> DoubleBuffer<R> {
+    pub fn new(mut reader: R) -> Result<DoubleBuffer<R>, LexError> {
+        let reload = true;
+        let mut position = Position::new();
+
+        // The coding declaration can be on the second line, after a first line longer than a buffer
+        let head = match read_head(&mut reader) {
+            Ok(head) => head,
+            Err(err) => return Err(LexError::Io { message: err.to_string(), span: None })
         };
+        let (encoding, bom) = detect_encoding(&head)?;
+        let begin = if bom { BOM.len() } else { 0 };
+        let forward = begin as isize - 1;
+        position.offset = begin;
 
+        let mut head = Cursor::new(head);
         let mut buffer = [0u8; 2 * BUFFER_SIZE];
 
-        match file.read(&mut buffer[0..BUFFER_A_EOF]) {
-            Ok(_) => (),
-            Err(_) => return Err("Couldn't read the file")
+        let n = match fill(&mut head.by_ref().chain(&mut reader), &mut buffer[0..BUFFER_SIZE]) {
+            Ok(n) => n,
+            Err(err) => return Err(LexError::Io { message: err.to_string(), span: None })
         };
+        let lengths = [n, 0];
+        let spill = Vec::new();
+        let replay = Vec::new();
 
-        Ok(DoubleBuffer { file, buffer, begin, forward })
+        Ok(DoubleBuffer { reader, head, buffer, lengths, spill, replay, begin, forward, reload, position, encoding, bom })
     }
 
-    pub fn get_lexeme(&mut self) -> String {
-        let mut lexeme = String::new();
-        let mut i = self.begin;
+    fn lexeme_bytes(&self) -> Vec<u8> {
+        let mut lexeme = self.spill.clone();
+        self.ring_bytes(self.lexeme_end(), &mut lexeme);
 
-        while i != (self.forward + 1) as usize {
-            if self.buffer[i] != 0u8 {
-                let c = self.buffer[i] as char;
-                lexeme.push(c);
-            }
+        lexeme
+    }
 
-            i = (i + 1) % (2 * BUFFER_SIZE);
+    // Append the bytes of the buffers from the begin pointer up to end
+    fn ring_bytes(&self, end: usize, bytes: &mut Vec<u8>) {
+        if self.begin <= end {
+            bytes.extend_from_slice(&self.buffer[self.begin..end]);
+        } else {
+            bytes.extend_from_slice(&self.buffer[self.begin..]);
+            bytes.extend_from_slice(&self.buffer[..end]);
         }
+    }
 
-        self.forward();
-        self.begin = self.forward as usize;
-        self.back();
+    // Number of bytes read since the start of the lexeme
+    fn lexeme_length(&self) -> usize {
+        self.spill.len() + (self.lexeme_end() + 2 * BUFFER_SIZE - self.begin) % (2 * BUFFER_SIZE)
+    }
 
-        lexeme
+
+
+    // Position that follows forward in the ring
+    fn after_forward(&self) -> usize {
+        (self.forward + 1) as usize % (2 * BUFFER_SIZE)
+    }
+
+    // The lexeme ends right before the position that follows forward, or before forward after the end of file
+    fn lexeme_end(&self) -> usize {
+        if self.past_end() {
+            self.forward as usize
+        } else {
+            self.after_forward()
+        }
+    }
+
+    fn io_error(&self, err: std::io::Error) -> LexError {
+        LexError::Io { message: err.to_string(), span: Some(Span::empty(self.position)) }
     }
+}
+
+impl<R: Read> ByteInput for DoubleBuffer<R> {
+    // Read the next byte, loading the other buffer when the current one is exhausted
+    fn next_byte(&mut self) -> Option<Result<u8, LexError>> {
+        if let Some(byte) = self.replay.pop() {
+            self.spill.push(byte);
+            return Some(Ok(byte));
+        }
+
+        // The end of file is only read once, there is nothing after it
+        if self.past_end() {
+            return None;
+        }
 
-    pub fn reject(&mut self) {
-        self.forward();
-        self.begin = self.forward as usize;
-        self.back();
+        let mut next = (self.forward + 1) as usize;
+
+        /*
+         * Crossing the end of a buffer means it was full, the file goes on in the other one.
+         * The other buffer is loaded unless forward went back over the boundary before.
+         */
+        if next.is_multiple_of(BUFFER_SIZE) & (self.forward >= 0) {
+            next %= 2 * BUFFER_SIZE;
+            let half = next / BUFFER_SIZE;
+
+            if self.reload {
+                /*
+                 * The lexeme may have started in the buffer about to be loaded, so the bytes read
+                 * of it in the buffers are added to the spill buffer and it goes on from the start
+                 * of the buffer. This way a lexeme can be longer than the buffers.
+                 */
+                if self.begin != next {
+                    let mut spill = std::mem::take(&mut self.spill);
+                    self.ring_bytes(next, &mut spill);
+                    self.spill = spill;
+                    self.begin = next;
+                }
+
+                let range = (half * BUFFER_SIZE)..((half + 1) * BUFFER_SIZE);
+                let mut reader = self.head.by_ref().chain(&mut self.reader);
+                self.lengths[half] = match fill(&mut reader, &mut self.buffer[range]) {
+                    Ok(n) => n,
+                    Err(err) => return Some(Err(self.io_error(err)))
+                };
+            }
+            self.reload = true;
+        }
+
+        // The end of the file is reached when forward passes the bytes read in its buffer
+        self.forward = next as isize;
+        if self.past_end() {
+            return None;
+        }
+
+        Some(Ok(self.buffer[next]))
     }
 
-    pub fn back(&mut self) {
+    fn back_byte(&mut self) {
+        // The bytes in the buffers are all gone back over, the byte is taken from the spill buffer
+        if !self.past_end() && (self.lexeme_end() == self.begin) {
+            if let Some(byte) = self.spill.pop() {
+                self.replay.push(byte);
+                return;
+            }
+        }
+
+        /*
+         * Going back over a buffer boundary means the buffer ahead is already loaded,
+         * so the next time forward crosses the boundary it mustn't be read again.
+         */
         let index = if self.forward == 0 {
-            (BUFFER_B_EOF - 1) as isize
-        } else if self.forward == (BUFFER_A_EOF + 1) as isize {
-            (BUFFER_A_EOF - 1) as isize
+            self.reload = false;
+            (2 * BUFFER_SIZE - 1) as isize
+        } else if self.forward == BUFFER_SIZE as isize {
+            self.reload = false;
+            (BUFFER_SIZE - 1) as isize
         } else {
-            self.forward - 1 as isize
+            self.forward - 1
         };
 
         self.forward = index;
     }
 
-    fn forward(&mut self) {
-        if self.forward as usize == BUFFER_A_EOF - 1 {
-            self.forward = self.forward + 2;
-        } else if self.forward as usize == BUFFER_B_EOF - 1 {
-            self.forward = 0;
+    // Last byte read of the lexeme, the one back_byte goes back over
+    fn last_byte(&self) -> Option<u8> {
+        if self.lexeme_end() == self.begin {
+            self.spill.last().copied()
         } else {
-            self.forward = self.forward + 1;
-        }
-    }
-
-    pub fn next(&mut self) -> Option<Result<char, &'static str>> {
-        self.forward = self.forward + 1;
-        let c = match self.buffer[self.forward as usize] {
-            /*
-             * If EOF (represented by the byte 0) is found, we need to find out if it's
-             * the end of file or the end of one of the buffers.
-             * End of file -> Stop analysis.
-             * End of buffer -> Load the other buffer and move the forward index.
-             */
-            0u8 => {
-                if self.forward as usize == BUFFER_A_EOF {
-                    // Load second buffer reading the next characters
-                    let n = match self.file.read(&mut self.buffer[BUFFER_SIZE..BUFFER_B_EOF]) {
-                        Ok(n) => (n),
-                        Err(_) => return Some(Err("Couldn't read the file"))
-                    };
-
-                    /* Check if we read less bytes than BUFFER_SIZE it means we reached the
-                     * end of file. We need to insert 0 after the last char to represent EOF.
-                    */
-                    if n < BUFFER_SIZE - 1 {
-                        self.buffer[BUFFER_A_EOF + n + 1] = 0u8;
-                    }
+            Some(self.buffer[self.forward as usize])
+        }
+    }
 
-                    // Move the index to the start of the second buffer
-                    self.forward = self.forward + 1;
-                } else if self.forward as usize == BUFFER_B_EOF {
-                    // Load first buffer reading the next characters
-                    let n = match self.file.read(&mut self.buffer[0..BUFFER_A_EOF]) {
-                        Ok(n) => (n),
-                        Err(_) => return Some(Err("Couldn't read the file"))
-                    };
-
-                    /* Check if we read less bytes than BUFFER_SIZE it means we reached the
-                    * end of file. We need to insert 0 after the last char to represent EOF.
-                   */
-                    if n < BUFFER_SIZE - 1 {
-                        self.buffer[n + 1] = 0u8;
-                    }
+    // Forward is on the position after the last byte read of the file
+    fn past_end(&self) -> bool {
+        if self.forward < 0 {
+            return false;
+        }
 
-                    // Move the index to the start of the first buffer
-                    self.forward = 0;
-                } else {
-                    // We reached the end of file return None to stop the loop
-                    return None;
-                }
-                self.buffer[self.forward as usize] as char
+        let forward = self.forward as usize;
+        forward % BUFFER_SIZE == self.lengths[forward / BUFFER_SIZE]
+    }
+
+    // Error for the character of the last bytes read
+    fn invalid_byte(&self, length: usize) -> LexError {
+        undecodable(&self.lexeme_bytes(), length, self.position, self.encoding)
+    }
+}
+
+// The buffers are loaded again as the source is read, the lexemes are copied out of them
+impl<'a, R: Read> InputSystem<'a> for DoubleBuffer<R> {
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn bom(&self) -> bool {
+        self.bom
+    }
+
+    fn get_lexeme(&mut self) -> Cow<'a, str> {
+        let (lexeme, end) = self.peek_lexeme();
+        self.position = end;
+        self.begin = self.lexeme_end();
+        self.spill.clear();
+
+        lexeme
+    }
+
+    fn peek_lexeme(&self) -> (Cow<'a, str>, Position) {
+        let bytes = self.lexeme_bytes();
+
+        // Characters are validated while reading them
+        let lexeme = self.encoding.decode(&bytes);
+
+        let mut end = self.position;
+        end.advance_str(&lexeme);
+        end.offset = self.position.offset + bytes.len();
+
+        (Cow::Owned(lexeme), end)
+    }
+
+    fn reject(&mut self) {
+        self.get_lexeme();
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+
+    fn back(&mut self) {
+        back_char(self, self.encoding);
+    }
+
+    fn mark(&self) -> Mark {
+        let end_of_file = if self.past_end() { 1 } else { 0 };
+        Mark(self.position.offset + self.lexeme_length() + end_of_file)
+    }
+
+    fn reset(&mut self, mark: Mark) {
+        assert!(mark.0 >= self.position.offset, "reset to a mark before the start of the lexeme");
+
+        // Going back a byte at a time keeps the buffers and the spill buffer as they were read
+        while self.mark() > mark {
+            self.back_byte();
+        }
+    }
+}
+
+/*
+ * Skip the UTF-8 byte order mark and look for a coding declaration in the first bytes of the
+ * source. A declaration of other encoding than UTF-8 after a BOM is an error, like in CPython.
+ */
+pub(crate) fn detect_encoding(bytes: &[u8]) -> Result<(Encoding, bool), LexError> {
+    let bom = bytes.starts_with(&BOM);
+    let mut start = Position::new();
+    if bom {
+        start.offset = BOM.len();
+    }
+
+    let encoding = match encoding::coding_cookie(&bytes[start.offset..], start) {
+        None => Encoding::Utf8,
+        Some((name, span)) => match Encoding::from_name(&name) {
+            Some(Encoding::Utf8) => Encoding::Utf8,
+            Some(_) if bom => return Err(LexError::EncodingConflict { name, span }),
+            Some(encoding) => encoding,
+            None => return Err(LexError::UnknownEncoding { name, span })
+        }
+    };
+
+    Ok((encoding, bom))
+}
+
+/*
+ * Error for the character of the last bytes of a lexeme, the bytes before them are valid.
+ * A NUL byte is never valid in the source, like in CPython.
+ */
+pub(crate) fn undecodable(lexeme: &[u8], length: usize, position: Position, encoding: Encoding) -> LexError {
+    let valid = &lexeme[..lexeme.len().saturating_sub(length)];
+    let byte = lexeme.get(valid.len()).copied().unwrap_or(0u8);
+
+    let mut start = position;
+    start.advance_str(&encoding.decode(valid));
+    start.offset = position.offset + valid.len();
+
+    let mut end = start;
+    end.offset += 1;
+    end.column += 1;
+
+    let span = Span::new(start, end);
+    match encoding {
+        _ if byte == 0u8 => LexError::NullByte { span },
+        Encoding::Utf8 => LexError::InvalidUtf8 { byte, span },
+        encoding => LexError::UndecodableByte { byte, encoding: encoding.name(), span },
+    }
+}
+
+/*
+ * Decode the next character of the input, both input systems read their characters with it. The
+ * bytes of a UTF-8 one may be split between both buffers of a double buffer.
+ */
+pub(crate) fn next_char(input: &mut impl ByteInput, encoding: Encoding) -> Option<Result<char, LexError>> {
+    let lead = match input.next_byte()? {
+        Ok(byte) => byte,
+        Err(err) => return Some(Err(err))
+    };
+
+    if lead == 0u8 {
+        return Some(Err(input.invalid_byte(1)));
+    }
+
+    // Every newline convention is read as '\n', "\r\n" being a single character
+    if lead == b'\r' {
+        match input.next_byte() {
+            Some(Ok(b'\n')) => (),
+            Some(Ok(_)) | None => input.back_byte(),
+            Some(Err(err)) => return Some(Err(err)),
+        }
+        return Some(Ok('\n'));
+    }
+
+    if encoding != Encoding::Utf8 {
+        return Some(encoding.decode_byte(lead).ok_or_else(|| input.invalid_byte(1)));
+    }
+
+    let length = match lead {
+        0x00..=0x7F => return Some(Ok(lead as char)),
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return Some(Err(input.invalid_byte(1)))
+    };
+
+    let mut bytes = [lead, 0u8, 0u8, 0u8];
+    for (read, byte) in bytes.iter_mut().enumerate().take(length).skip(1) {
+        *byte = match input.next_byte() {
+            Some(Ok(continuation)) if continuation & 0xC0 == 0x80 => continuation,
+            Some(Err(err)) => return Some(Err(err)),
+            Some(Ok(_)) => {
+                // The byte that isn't a continuation starts the next character
+                input.back_byte();
+                return Some(Err(input.invalid_byte(read)));
             }
-            _ => self.buffer[self.forward as usize] as char
+            None => return Some(Err(input.invalid_byte(read)))
         };
+    }
 
-        Some(Ok(c))
+    match std::str::from_utf8(&bytes[0..length]) {
+        Ok(c) => c.chars().next().map(Ok),
+        Err(_) => Some(Err(input.invalid_byte(length)))
     }
-}
\ No newline at end of file
+}
+
+// Go back over the last character read, "\r\n" being a single one
+pub(crate) fn back_char(input: &mut impl ByteInput, encoding: Encoding) {
+    // Forward is after the last byte of the file, there is no character to go back over
+    if input.past_end() {
+        input.back_byte();
+        return;
+    }
+
+    // Go back over the continuation bytes of the current character and its leading byte
+    while let Some(byte) = input.last_byte() {
+        input.back_byte();
+
+        if (encoding != Encoding::Utf8) | (byte & 0xC0 != 0x80) {
+            // A "\r\n" was read as a single character
+            if (byte == b'\n') && (input.last_byte() == Some(b'\r')) {
+                input.back_byte();
+            }
+            break;
+        }
+    }
+}
+
+// Read the first two lines of the source, or all of it when it's shorter
+fn read_head(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; BUFFER_SIZE];
+    // Lines ended in the bytes before scanned, each byte is only looked at once
+    let mut lines = 0;
+    let mut scanned = 0;
+
+    while lines < 2 {
+        let n = fill(reader, &mut chunk)?;
+        head.extend_from_slice(&chunk[0..n]);
+
+        lines += line_ends(&head[scanned..]);
+        scanned = head.len() - usize::from(head.ends_with(b"\r"));
+
+        if n < BUFFER_SIZE {
+            break;
+        }
+    }
+
+    Ok(head)
+}
+
+// Number of lines ended in the bytes, whatever their newline convention
+fn line_ends(bytes: &[u8]) -> usize {
+    // A "\r" at the end may be the start of a "\r\n" not read yet
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+
+    bytes.iter()
+        .enumerate()
+        .filter(|(i, byte)| (**byte == b'\n') | ((**byte == b'\r') & (bytes.get(i + 1) != Some(&b'\n'))))
+        .count()
+}
+
+/*
+ * Read until the buffer is full or the source ends. Pipes and sockets may return less bytes than
+ * asked before the end, which would be taken for the end of file.
+ */
+fn fill(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+
+    while n < buffer.len() {
+        match reader.read(&mut buffer[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(n)
+}
+
+impl<R: Read> Iterator for DoubleBuffer<R> {
+    type Item = Result<char, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_char(self, self.encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexical_analyzer::LexicalAnalyzer;
+    use crate::source_buffer::SourceBuffer;
+    use crate::token;
+
+    // Text and span of the tokens of the source, in trivia mode so every byte is in a token
+    fn tokens<'s>(input: impl InputSystem<'s>) -> Vec<(String, Span)> {
+        let mut symbol_table = token::symbol_table();
+        let mut lexical_analyzer = LexicalAnalyzer::new(input, &mut symbol_table);
+        lexical_analyzer.set_trivia(true);
+
+        lexical_analyzer
+            .map(|token| token.unwrap())
+            .map(|token| (token.kind.lexeme().to_string(), token.span))
+            .collect()
+    }
+
+    // Both input systems read the same tokens, which are the text of the source in their span
+    fn assert_tokens(text: &str) -> Vec<(String, Span)> {
+        let tokens = tokens(DoubleBuffer::from_text(text).unwrap());
+        assert_eq!(tokens, self::tokens(SourceBuffer::from_text(text).unwrap()));
+
+        for (lexeme, span) in &tokens {
+            assert_eq!(lexeme, &text[span.start.offset..span.end.offset]);
+        }
+
+        tokens
+    }
+
+    #[test]
+    fn long_string() {
+        let string = format!("\"{}\"", "abcdefghi".repeat(50));
+        let text = format!("s = {}\nt = 1\n", string);
+
+        let tokens = assert_tokens(&text);
+        let (lexeme, span) = &tokens[4];
+        assert_eq!(lexeme, &string);
+        assert_eq!(string.len(), 452);
+        assert_eq!((span.end.line, span.end.column), (1, 456));
+    }
+
+    #[test]
+    fn docstring_with_four_byte_characters() {
+        let docstring = format!("\"\"\"{}\n{}\n\"\"\"", "🐍 snake 🐍\n".repeat(20), "𝔘𝔫𝔦𝔠𝔬𝔡𝔢".repeat(10));
+        let text = format!("def f():\n    {}\n    return '𝓍'\n", docstring);
+
+        let tokens = assert_tokens(&text);
+        let (lexeme, span) = tokens.iter().find(|(lexeme, _)| lexeme.starts_with("\"\"\"")).unwrap();
+        assert_eq!(lexeme, &docstring);
+        assert_eq!((span.start.line, span.end.line), (2, 24));
+        assert_eq!(span.end.column, 3);
+    }
+
+    #[test]
+    fn long_identifier() {
+        let identifier = "ñame_".repeat(60) + "𝔁";
+        let text = format!("{} = {}\n", identifier, identifier);
+
+        let tokens = assert_tokens(&text);
+        assert_eq!(tokens[0].0, identifier);
+        assert_eq!(tokens[4].0, identifier);
+        assert_eq!(tokens[4].1.start.column, identifier.chars().count() + 3);
+    }
+
+    // Generator of pseudorandom numbers, xorshift, the tests must read the same every time
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    // Characters of the text as the input systems read them, with the offset of each one and the end
+    fn characters(text: &str) -> (Vec<char>, Vec<usize>) {
+        let mut characters = Vec::new();
+        let mut offsets = Vec::new();
+        let mut iter = text.char_indices().peekable();
+
+        while let Some((offset, c)) = iter.next() {
+            if (c == '\r') && (iter.peek().map(|(_, c)| *c) == Some('\n')) {
+                iter.next();
+            }
+            characters.push(if c == '\r' { '\n' } else { c });
+            offsets.push(offset);
+        }
+        offsets.push(text.len());
+
+        (characters, offsets)
+    }
+
+    // Read the text taking random steps, going back with back, reset and peek across the buffers
+    fn random_checkpoints<'s>(mut input: impl InputSystem<'s>, text: &str, seed: u64) {
+        let (characters, offsets) = characters(text);
+        let mut random = Random(seed);
+        let mut begin = 0;
+        let mut forward = 0;
+
+        let next = |input: &mut dyn Iterator<Item = Result<char, LexError>>| input.next().map(Result::unwrap);
+
+        while forward < characters.len() {
+            match random.below(6) {
+                0 => {
+                    let n = random.below(300);
+                    assert_eq!(input.peek(n).map(Result::unwrap), characters.get(forward + n).copied());
+                }
+                1 => {
+                    let mark = input.mark();
+                    for i in 0..random.below(300) {
+                        assert_eq!(next(&mut input), characters.get(forward + i).copied());
+                    }
+                    input.reset(mark);
+                }
+                2 if forward > begin => {
+                    input.back();
+                    forward -= 1;
+                }
+                3 => {
+                    assert_eq!(input.get_lexeme(), &text[offsets[begin]..offsets[forward]]);
+                    assert_eq!(input.position().offset, offsets[forward]);
+                    begin = forward;
+                }
+                _ => {
+                    assert_eq!(next(&mut input), Some(characters[forward]));
+                    forward += 1;
+                }
+            }
+        }
+
+        assert_eq!(input.peek(0).map(Result::unwrap), None);
+        assert_eq!(input.get_lexeme(), &text[offsets[begin]..]);
+    }
+
+    #[test]
+    fn random_mark_reset_and_peek() {
+        let line = "naïve = '中文' + \"🐍\" * 3  # comment\r\n\tpass\rx = 0\n";
+        let text = line.repeat(40);
+
+        for seed in 1..=20 {
+            random_checkpoints(DoubleBuffer::from_text(&text).unwrap(), &text, seed);
+            random_checkpoints(SourceBuffer::from_text(&text).unwrap(), &text, seed);
+        }
+    }
+    // Reset to a mark made before the lexeme taken since
+    fn reset_before_lexeme<'s>(mut input: impl InputSystem<'s>) {
+        let mark = input.mark();
+        input.nth(3);
+        input.get_lexeme();
+        input.reset(mark);
+    }
+
+    #[test]
+    #[should_panic(expected = "before the start of the lexeme")]
+    fn double_buffer_reset_before_lexeme() {
+        reset_before_lexeme(DoubleBuffer::from_text("x = 1\n").unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "before the start of the lexeme")]
+    fn source_buffer_reset_before_lexeme() {
+        reset_before_lexeme(SourceBuffer::from_text("x = 1\n").unwrap());
+    }
+}
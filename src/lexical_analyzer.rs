@@ -1,61 +1,218 @@
-use std::collections::HashMap;
-use crate::{Config, DoubleBuffer};
-use crate::token::Token;
+use std::collections::{HashMap, VecDeque};
+use std::borrow::Cow;
+use unicode_ident::{is_xid_continue, is_xid_start};
+use unicode_normalization::UnicodeNormalization;
+use crate::encoding::Encoding;
+use crate::error::{LexError, LexWarning};
+use crate::escape;
+use crate::input_system::{DoubleBuffer, InputSystem};
+use crate::source_buffer::SourceBuffer;
+use crate::span::{Span, TAB_SIZE};
+use crate::token::{StringPrefix, Token, TokenKind};
 
 enum State {
     Init,
+    Indent,
+    NewLine,
+    End,
     Id,
-    Comment(CommentState),
+    Prefix(PrefixState),
+    Comment,
+    Continuation,
+    Trivia(TriviaState),
     String(StringState),
-    MultilineComment(MultilineCommentState),
+    FString(FStringState),
     Integer(IntegerState),
     Float(FloatState),
+    Imaginary,
     Operator(OperatorState),
     Delimiter(DelimiterState),
 }
 
-enum CommentState {
-    Q0,
-    Q1,
+/*
+ * Text without meaning returned in trivia mode. Whitespace is a run of spaces, tabs and form
+ * feeds, Continuation a backslash and the newline it joins, NL a newline that doesn't end the
+ * logical line and BlankLine the newline of a line without code.
+ */
+enum TriviaState {
+    Whitespace,
+    Continuation,
+    NL,
+    BlankLine,
+}
+
+enum PrefixState {
+    R,
+    U,
+    B,
+    F,
+    RawBytes,
+    RawFormatted,
+}
+
+impl PrefixState {
+    fn string_prefix(&self) -> StringPrefix {
+        match self {
+            PrefixState::R => StringPrefix::Raw,
+            PrefixState::U => StringPrefix::Unicode,
+            PrefixState::B => StringPrefix::Bytes,
+            PrefixState::F => StringPrefix::Formatted,
+            PrefixState::RawBytes => StringPrefix::RawBytes,
+            PrefixState::RawFormatted => StringPrefix::RawFormatted,
+        }
+    }
+}
 
+#[derive(Clone, Copy)]
+enum Quote {
+    Single,
+    Double,
 }
 
+/*
+ * Q0 is the opening quote and Empty two consecutive quotes, which are either an empty string or
+ * the start of a triple-quoted one. Long strings are triple-quoted, LongQ1 and LongQ2 count the
+ * closing quotes found so far. The escape states take the character after a backslash whatever
+ * it is, so an escaped quote doesn't close the string.
+ */
 enum StringState {
-    SingleQuoteQ0,
-    SingleQuoteQ1,
-    DoubleQuoteQ0,
-    DoubleQuoteQ1,
-    DoubleQuoteQ2,
+    Q0(Quote),
+    Empty(Quote),
+    Short(Quote),
+    ShortEscape(Quote),
+    Long(Quote),
+    LongQ1(Quote),
+    LongQ2(Quote),
+    LongEscape(Quote),
+    Complete,
+}
+
+/*
+ * Q0 and Empty read the opening quotes of an f-string like their string counterparts, Opened is a
+ * triple-quoted start. The remaining states read the literal parts: Start is the beginning of a
+ * part and Middle a part with some text. LBrace and RBrace check for doubled braces, Quote1 and
+ * Quote2 count closing quotes of triple-quoted f-strings. Field, SpecEnd and End are complete.
+ */
+enum FStringState {
+    Q0(Quote),
+    Empty(Quote),
+    Opened(Quote),
+    Start,
+    Middle,
+    Escape,
+    NamedEscape,
+    LBrace,
+    RBrace,
+    Quote1,
+    Quote2,
+    Field,
+    SpecEnd,
+    End,
+}
+
+/*
+ * Modes stacked while lexing f-strings. FString reads the literal parts of the f-string and keeps
+ * the FSTRING_START token for errors, Replacement the expression of a replacement field, counting
+ * the brackets opened inside it, and FormatSpec the format specifier after the colon.
+ */
+enum Mode<'s> {
+    FString { quote: Quote, triple: bool, raw: bool, span: Span, text: Cow<'s, str> },
+    Replacement { depth: usize },
+    FormatSpec,
 }
 
-enum MultilineCommentState {
-    Q0,
-    Q1,
-    Q2,
+/*
+ * Indentation of an open block. The column is also measured with tabs of a single column,
+ * like CPython does, to find indentation that depends on the size of the tabs.
+ */
+struct Indentation {
+    column: usize,
+    alt_column: usize,
+    text: String,
 }
 
+#[derive(Clone, Copy)]
+enum Radix {
+    Binary,
+    Octal,
+    Hexadecimal,
+}
+
+impl Radix {
+    fn base(&self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Hexadecimal => 16,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Radix::Binary => "binary",
+            Radix::Octal => "octal",
+            Radix::Hexadecimal => "hexadecimal",
+        }
+    }
+}
+
+/*
+ * Zero reads decimal integers made only of zeros, LeadingZero a zero followed by other digits,
+ * which is only valid as the digit part of a float or imaginary number. The underscore states
+ * require a digit after a single underscore (PEP 515).
+ */
+#[derive(Clone, Copy)]
 enum IntegerState {
-    Decinteger,
-    HexintegerQ0,
-    HexintegerQ1,
+    Decimal,
+    DecimalUnderscore,
+    Zero,
+    ZeroUnderscore,
+    LeadingZero,
+    LeadingZeroUnderscore,
+    RadixPrefix(Radix),
+    RadixDigits(Radix),
+    RadixUnderscore(Radix),
 }
 
+/*
+ * DotStart is a dot that may still be a delimiter and Point a digit part followed by a dot.
+ */
+#[derive(Clone, Copy)]
 enum FloatState {
-    Q0,
-    Q1,
     DotStart,
+    Point,
+    Fraction,
+    FractionUnderscore,
     Exponent,
+    ExponentSign,
+    ExponentDigits,
+    ExponentUnderscore,
 }
 
 enum OperatorState {
-    PlusMinus,
+    Plus,
+    Minus,
     Asterisk,
+    DoubleAsterisk,
+    Slash,
+    DoubleSlash,
+    Percent,
+    At,
+    Ampersand,
+    VerticalBar,
+    Caret,
     Less,
+    DoubleLess,
+    Greater,
+    DoubleGreater,
+    Complete,
 }
 
 enum DelimiterState {
-    General,
     Equal,
+    Colon,
+    Exclamation,
+    Complete,
 }
 
 enum Event {
@@ -63,14 +220,23 @@ enum Event {
     NonZeroDigit,
     Zero,
     Letter,
+    IdContinue,
     X,
     Underscore,
     Hashtag,
     NewLine,
+    Tab,
     Plus,
     Minus,
     Asterisk,
     Slash,
+    Percent,
+    At,
+    Ampersand,
+    VerticalBar,
+    Caret,
+    Tilde,
+    Exclamation,
     Less,
     Greater,
     OpeningParenthesis,
@@ -81,77 +247,470 @@ enum Event {
     ClosingCurlyBracket,
     Coma,
     Colon,
+    Semicolon,
     Dot,
     Equal,
     Exponent,
     SingleQuote,
     DoubleQuote,
+    Backslash,
     Space,
     Other,
 }
 
-pub struct LexicalAnalyzer<'a> {
+fn closing_bracket(opening: char) -> char {
+    match opening {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+// Column of the indentation of a line, a form feed resets the count like in CPython
+fn indentation_column(indentation: &str, tab_size: usize) -> usize {
+    indentation.chars().fold(0, |column, c| match c {
+        '\t' => (column / tab_size + 1) * tab_size,
+        '\x0c' => 0,
+        _ => column + 1,
+    })
+}
+
+/*
+ * Whether the character can start (or continue) an identifier. Like CPython, non-ASCII
+ * characters are checked in NFKC normal form, so '₁' continues an identifier as '1' does.
+ */
+fn identifier(c: char, start: bool) -> bool {
+    if c.is_ascii() {
+        return if start { is_xid_start(c) } else { is_xid_continue(c) };
+    }
+
+    let mut normalized = std::iter::once(c).nfkc();
+    match normalized.next() {
+        Some(first) if start => is_xid_start(first) & normalized.all(is_xid_continue),
+        Some(first) => is_xid_continue(first) & normalized.all(is_xid_continue),
+        None => false,
+    }
+}
+
+// The tokens borrow their text from a source that lives for 's, the symbol table is borrowed for 'a
+pub struct LexicalAnalyzer<'a, 's, I = DoubleBuffer> {
     state: State,
     event: Event,
-    double_buffer: DoubleBuffer,
-    symbol_table: &'a mut HashMap<String, Token>,
-    line_counter: u32,
+    input: I,
+    symbol_table: &'a mut HashMap<String, TokenKind<'static>>,
+    indent_stack: Vec<Indentation>,
+    pending: VecDeque<Token<'s>>,
+    prefix: StringPrefix,
+    modes: Vec<Mode<'s>>,
+    bracket_stack: Vec<(char, Span)>,
+    warnings: Vec<LexWarning>,
+    errors: Vec<LexError>,
+    recovery: bool,
+    trivia: bool,
+    logical_line: bool,
+    eof: bool,
 }
 
-impl LexicalAnalyzer<'_> {
-    pub fn new<'a>(config: Config, symbol_table: &'a mut HashMap<String, Token>) -> Result<LexicalAnalyzer, &'static str> {
-        let state = State::Init;
+impl<'a, 's, I: InputSystem<'s>> LexicalAnalyzer<'a, 's, I> {
+    // Analyze the source of an input system, a double buffer reading it or a source buffer holding it
+    pub fn new(input: I, symbol_table: &'a mut HashMap<String, TokenKind<'static>>) -> LexicalAnalyzer<'a, 's, I> {
+        let state = State::Indent;
         let event = Event::None;
-        let double_buffer = DoubleBuffer::new(config)?;
-        let line_counter = 1;
+        let indent_stack = vec![Indentation { column: 0, alt_column: 0, text: String::new() }];
+        let pending = VecDeque::new();
+        let prefix = StringPrefix::None;
+        let modes = Vec::new();
+        let bracket_stack = Vec::new();
+        let warnings = Vec::new();
+        let errors = Vec::new();
+        let recovery = false;
+        let trivia = false;
+        let logical_line = false;
+        let eof = false;
+
+        LexicalAnalyzer {
+            state,
+            event,
+            input,
+            symbol_table,
+            indent_stack,
+            pending,
+            prefix,
+            modes,
+            bracket_stack,
+            warnings,
+            errors,
+            recovery,
+            trivia,
+            logical_line,
+            eof,
+        }
+    }
+
+    // Encoding the source is read in
+    pub fn encoding(&self) -> Encoding {
+        self.input.encoding()
+    }
+
+    // The source starts with a byte order mark, which no token includes
+    pub fn bom(&self) -> bool {
+        self.input.bom()
+    }
+
+    // Take the warnings found since the last call, like invalid escape sequences
+    pub fn take_warnings(&mut self) -> Vec<LexWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /*
+     * In recovery mode an error doesn't stop the analysis. The bad text is returned as an Error
+     * token and the error is kept to be taken with take_errors.
+     */
+    pub fn set_recovery(&mut self, recovery: bool) {
+        self.recovery = recovery;
+    }
+
+    /*
+     * In trivia mode comments, whitespace, continuation backslashes and the newlines that don't
+     * end a logical line are returned as Comment, Whitespace and NL tokens instead of skipped.
+     */
+    pub fn set_trivia(&mut self, trivia: bool) {
+        self.trivia = trivia;
+    }
+
+    // Take the errors found since the last call in recovery mode
+    pub fn take_errors(&mut self) -> Vec<LexError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    // Span and text of what has been read of the current lexeme, for errors
+    fn offending(&self) -> (Span, String) {
+        let (text, end) = self.input.peek_lexeme();
+        (Span::new(self.input.position(), end), text.into_owned())
+    }
+
+    fn error(&self, err: impl Fn(Span, String) -> LexError) -> LexError {
+        let (span, text) = self.offending();
+        err(span, text)
+    }
+
+    // Error found on the character just read, which isn't part of the text, like the end of the line
+    fn error_before(&mut self, err: impl Fn(Span, String) -> LexError) -> LexError {
+        self.input.back();
+        self.error(err)
+    }
+
+    // Error for a number that can't end in the current state
+    fn number_error(&self) -> Option<LexError> {
+        let (span, text) = self.offending();
+
+        let err = match self.state {
+            State::Integer(IntegerState::LeadingZero) => LexError::LeadingZeros { span, text },
+            State::Integer(IntegerState::RadixPrefix(radix)) |
+            State::Integer(IntegerState::RadixUnderscore(radix)) =>
+                LexError::InvalidNumber { radix: radix.name(), span, text },
+            State::Integer(IntegerState::DecimalUnderscore) |
+            State::Integer(IntegerState::ZeroUnderscore) |
+            State::Integer(IntegerState::LeadingZeroUnderscore) |
+            State::Float(FloatState::FractionUnderscore) |
+            State::Float(FloatState::ExponentUnderscore) => LexError::InvalidNumber { radix: "decimal", span, text },
+            State::Float(FloatState::Exponent) |
+            State::Float(FloatState::ExponentSign) => LexError::MissingExponent { span, text },
+            _ => return None,
+        };
+
+        Some(err)
+    }
+
+    /*
+     * A letter right after a number is an error, unless it starts one of the keywords that follow
+     * a number in valid code, like the if of `1if x else 2`. CPython only warns about those.
+     */
+    fn keyword_after_number(&mut self, c: char) -> bool {
+        let rests: &[&str] = match c {
+            'a' => &["nd"],
+            'e' => &["lse"],
+            'f' => &["or"],
+            'i' => &["f", "n", "s"],
+            'n' => &["ot"],
+            'o' => &["r"],
+            _ => return false,
+        };
+
+        rests.iter().any(|rest| rest.chars().enumerate().all(|(n, r)| matches!(self.input.peek(n), Some(Ok(c)) if c == r)))
+    }
+
+    // Error for a number ended by the character just read, which isn't part of its text
+    fn number_error_before(&mut self) -> Option<LexError> {
+        self.input.back();
+        let err = self.number_error();
+        self.input.next();
+
+        err
+    }
+
+    // The number ends before the keyword that follows it, with a warning unless it's invalid
+    fn number_before_keyword(&mut self, radix: &'static str) -> Option<LexError> {
+        self.input.back();
+        let err = self.number_error();
+        if err.is_none() {
+            let (span, text) = self.offending();
+            self.warnings.push(LexWarning::InvalidNumber { radix, span, text });
+        }
+        self.input.next();
+
+        err
+    }
+
+    // Tokens start in the literal part of an f-string while one is open
+    fn start_state(&self) -> State {
+        match self.modes.last() {
+            Some(Mode::FString { .. }) | Some(Mode::FormatSpec) => State::FString(FStringState::Start),
+            _ => State::Init,
+        }
+    }
+
+    // Quote, triple quoting and rawness of the innermost f-string
+    fn fstring(&self) -> Option<(Quote, bool, bool)> {
+        self.modes.iter().rev().find_map(|mode| match mode {
+            Mode::FString { quote, triple, raw, .. } => Some((*quote, *triple, *raw)),
+            _ => None,
+        })
+    }
+
+    // Whether the line read in the Indent state starts with whitespace
+    fn indented(&self) -> bool {
+        self.input.peek_lexeme().0.starts_with([' ', '\t', '\x0c'])
+    }
+
+    /*
+     * Newlines inside brackets or replacement fields don't end the logical line, except in the
+     * replacement fields of a single-quoted f-string, which ends with its line.
+     */
+    fn implicit_line_joining(&self) -> bool {
+        match self.fstring() {
+            Some((_, triple, _)) => triple,
+            None => !self.bracket_stack.is_empty(),
+        }
+    }
+
+    // Error for the innermost f-string, still open at the end of its line or of the file
+    fn unterminated_fstring(&self) -> LexError {
+        let opening = self.modes.iter().rev().find_map(|mode| match mode {
+            Mode::FString { span, text, .. } => Some((*span, text.to_string())),
+            _ => None,
+        });
+        let (span, text) = opening.unwrap();
+
+        LexError::UnterminatedFString { span: Span::new(span.start, self.input.position()), text }
+    }
+
+    /*
+     * Keep the stack of open brackets, a closing bracket must match the last one opened. The
+     * expression of a replacement field can't close the brackets opened before the field.
+     */
+    fn brackets(&mut self, token: &Token) -> Result<(), LexError> {
+        let lexeme = match &token.kind {
+            TokenKind::Delimiter(lexeme) => lexeme,
+            _ => return Ok(()),
+        };
+        let field = matches!(self.modes.last(), Some(Mode::Replacement { depth: 0 }));
+
+        let closing = match lexeme.as_ref() {
+            "(" | "[" | "{" => {
+                self.bracket_stack.push((lexeme.chars().next().unwrap(), token.span));
+                return Ok(());
+            }
+            // The closing brace of a replacement field isn't a bracket of the expression
+            "}" if field => return Ok(()),
+            ")" | "]" | "}" => lexeme.chars().next().unwrap(),
+            _ => return Ok(()),
+        };
+
+        let span = token.span;
+        let text = lexeme.to_string();
+        match if field { None } else { self.bracket_stack.pop() } {
+            None => Err(LexError::UnmatchedBracket { span, text }),
+            Some((opening, opening_span)) if closing_bracket(opening) != closing => Err(LexError::MismatchedBracket {
+                span,
+                text,
+                opening,
+                opening_span,
+            }),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /*
+     * Keep the mode stack in step with the tokens of a replacement field. Brackets nest inside
+     * the expression, and at its top level a closing brace ends the field while a colon starts
+     * the format specifier.
+     */
+    fn replacement_field(&mut self, token: &TokenKind) {
+        let depth = match self.modes.last_mut() {
+            Some(Mode::Replacement { depth }) => depth,
+            _ => return,
+        };
+
+        if let TokenKind::Delimiter(lexeme) = token {
+            match lexeme.as_ref() {
+                "(" | "[" | "{" => *depth += 1,
+                ")" | "]" if *depth > 0 => *depth -= 1,
+                "}" if *depth > 0 => *depth -= 1,
+                "}" => {
+                    self.modes.pop();
+                }
+                ":" if *depth == 0 => self.modes.push(Mode::FormatSpec),
+                _ => (),
+            }
+        }
+    }
+
+    /*
+     * Compare the indentation of a new logical line against the indentation stack.
+     * A deeper level pushes and returns an INDENT, a shallower one pops every level above it
+     * queueing a DEDENT for each. Returns None when there is nothing to emit.
+     */
+    fn indentation(&mut self) -> Option<Result<Token<'s>, LexError>> {
+        let start = self.input.position();
+        let lexeme = self.input.get_lexeme();
+        let end = self.input.position();
+        let span = Span::new(start, end);
+        let column = indentation_column(&lexeme, TAB_SIZE);
+        let alt_column = indentation_column(&lexeme, 1);
+
+        self.state = State::Init;
+
+        // The indentation must compare the same way whatever the size of the tabs
+        let tab_error = |block: &Indentation, text: String| LexError::InconsistentTabs {
+            span,
+            text,
+            block: block.text.clone(),
+        };
+
+        let mut top = self.indent_stack.last().unwrap();
+        if column > top.column {
+            if alt_column <= top.alt_column {
+                return Some(Err(tab_error(top, lexeme.into_owned())));
+            }
+
+            self.indent_stack.push(Indentation { column, alt_column, text: lexeme.to_string() });
+            return Some(Ok(Token::new(TokenKind::Indent(lexeme), span)));
+        }
+
+        while column < top.column {
+            self.indent_stack.pop();
+            self.pending.push_back(Token::new(TokenKind::Dedent, Span::empty(end)));
+            top = self.indent_stack.last().unwrap();
+        }
+
+        if column != top.column {
+            return Some(Err(LexError::InconsistentDedent { span, text: lexeme.into_owned() }));
+        }
+
+        if alt_column != top.alt_column {
+            return Some(Err(tab_error(top, lexeme.into_owned())));
+        }
+
+        // Without an INDENT the indentation goes before the DEDENTs as whitespace
+        if self.trivia & !lexeme.is_empty() {
+            self.pending.push_front(Token::new(TokenKind::Whitespace(lexeme), Span::new(start, end)));
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+
+    // Close the last logical line and every open indentation level
+    fn end_of_file(&mut self) -> Option<Result<Token<'s>, LexError>> {
+        // The brackets still open can't be closed anymore
+        if let Some((opening, span)) = self.bracket_stack.pop() {
+            return Some(Err(LexError::UnclosedBracket { span, text: opening.to_string() }));
+        }
+
+        // Neither can a replacement field
+        if !self.modes.is_empty() {
+            return Some(Err(self.unterminated_fstring()));
+        }
+
+        let span = Span::empty(self.input.position());
 
+        if self.logical_line {
+            self.logical_line = false;
+            self.pending.push_back(Token::new(TokenKind::NewLine(Cow::Borrowed("")), span));
+        }
+
+        while self.indent_stack.len() > 1 {
+            self.indent_stack.pop();
+            self.pending.push_back(Token::new(TokenKind::Dedent, span));
+        }
 
-        Ok(LexicalAnalyzer { state, event, double_buffer, symbol_table, line_counter })
+        self.pending.push_back(Token::new(TokenKind::EndMarker, span));
+        self.state = State::End;
+
+        self.pending.pop_front().map(Ok)
     }
 }
 
-impl Iterator for LexicalAnalyzer<'_> {
-    type Item = Result<Token, String>;
+impl<'s, I: InputSystem<'s>> LexicalAnalyzer<'_, 's, I> {
+    fn next_token(&mut self) -> Option<Result<Token<'s>, LexError>> {
+        // Tokens queued by a previous call (DEDENTs) go first
+        if let Some(token) = self.pending.pop_front() {
+            return Some(Ok(token));
+        }
+
+        if let State::End = self.state {
+            return None;
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
         loop {
 
             // Get the next character
-            let c = match self.double_buffer.next() {
-                None => return None,
+            let c = match self.input.next() {
+                None => {
+                    // Keep the forward pointer on the last character so EOF is found again
+                    self.input.back();
+                    self.eof = true;
+                    break;
+                }
                 Some(c) => match c {
                     Ok(c) => c,
-                    Err(err) => return Some(Err(String::from(err)))
+                    Err(err) => return Some(Err(err))
                 }
             };
 
             // Transform character into event
             self.event =
-                if c.is_alphabetic() {
-                    if c != 'e' {
-                        Event::Letter
-                    } else if (c == 'x') | (c == 'X') {
-                        Event::X
-                    } else {
-                        Event::Exponent
-                    }
-                } else if c.is_numeric() {
+                if c.is_ascii_digit() {
                     if c != '0' {
                         Event::NonZeroDigit
                     } else {
                         Event::Zero
                     }
+                } else if identifier(c, true) {
+                    // Identifiers follow PEP 3131
+                    if (c == 'e') | (c == 'E') {
+                        Event::Exponent
+                    } else if (c == 'x') | (c == 'X') {
+                        Event::X
+                    } else {
+                        Event::Letter
+                    }
+                } else if (c != '_') & identifier(c, false) {
+                    Event::IdContinue
                 } else if c == '#' {
                     Event::Hashtag
                 } else if c == '\n' {
-                    self.line_counter = self.line_counter + 1;
                     Event::NewLine
+                } else if c == '\t' {
+                    Event::Tab
                 } else if c == '_' {
                     Event::Underscore
                 } else if c == '\'' {
                     Event::SingleQuote
                 } else if c == '"' {
                     Event::DoubleQuote
+                } else if c == '\\' {
+                    Event::Backslash
                 } else if c == '+' {
                     Event::Plus
                 } else if c == '-' {
@@ -160,6 +719,20 @@ impl Iterator for LexicalAnalyzer<'_> {
                     Event::Asterisk
                 } else if c == '/' {
                     Event::Slash
+                } else if c == '%' {
+                    Event::Percent
+                } else if c == '@' {
+                    Event::At
+                } else if c == '&' {
+                    Event::Ampersand
+                } else if c == '|' {
+                    Event::VerticalBar
+                } else if c == '^' {
+                    Event::Caret
+                } else if c == '~' {
+                    Event::Tilde
+                } else if c == '!' {
+                    Event::Exclamation
                 } else if c == '<' {
                     Event::Less
                 } else if c == '>' {
@@ -180,11 +753,13 @@ impl Iterator for LexicalAnalyzer<'_> {
                     Event::Coma
                 } else if c == ':' {
                     Event::Colon
+                } else if c == ';' {
+                    Event::Semicolon
                 } else if c == '.' {
                     Event::Dot
                 } else if c == '=' {
                     Event::Equal
-                } else if c == ' ' {
+                } else if (c == ' ') | (c == '\x0c') {
                     Event::Space
                 } else {
                     Event::Other
@@ -194,20 +769,59 @@ impl Iterator for LexicalAnalyzer<'_> {
             match self.state {
                 State::Init => {
                     match self.event {
-                        Event::Letter | Event::Exponent | Event::X => self.state = State::Id,
-                        Event::Hashtag => self.state = State::Comment(CommentState::Q0),
-                        Event::SingleQuote => self.state = State::String(StringState::SingleQuoteQ0),
-                        Event::DoubleQuote => self.state = State::String(StringState::DoubleQuoteQ0),
-                        Event::NonZeroDigit => self.state = State::Integer(IntegerState::Decinteger),
-                        Event::Zero => self.state = State::Integer(IntegerState::HexintegerQ0),
+                        // Letters that may start a string prefix
+                        Event::Letter if (c == 'r') | (c == 'R') => self.state = State::Prefix(PrefixState::R),
+                        Event::Letter if (c == 'u') | (c == 'U') => self.state = State::Prefix(PrefixState::U),
+                        Event::Letter if (c == 'b') | (c == 'B') => self.state = State::Prefix(PrefixState::B),
+                        Event::Letter if (c == 'f') | (c == 'F') => self.state = State::Prefix(PrefixState::F),
+                        Event::Letter | Event::Exponent | Event::X |
+                        Event::Underscore => self.state = State::Id,
+                        Event::Hashtag => {
+                            self.state = State::Comment;
+                            if !self.trivia {
+                                self.input.reject();
+                            }
+                        }
+                        Event::NewLine if self.implicit_line_joining() & self.trivia => {
+                            self.state = State::Trivia(TriviaState::NL);
+                            break;
+                        }
+                        Event::NewLine if self.implicit_line_joining() => self.input.reject(),
+                        Event::NewLine if !self.modes.is_empty() => {
+                            self.input.back();
+                            return Some(Err(self.unterminated_fstring()));
+                        }
+                        Event::Backslash => self.state = State::Continuation,
+                        Event::NewLine => {
+                            if self.logical_line {
+                                self.state = State::NewLine;
+                                break;
+                            }
+                            if self.trivia {
+                                self.state = State::Trivia(TriviaState::BlankLine);
+                                break;
+                            }
+                            self.state = State::Indent;
+                            self.input.reject();
+                        }
+                        Event::SingleQuote => self.state = State::String(StringState::Q0(Quote::Single)),
+                        Event::DoubleQuote => self.state = State::String(StringState::Q0(Quote::Double)),
+                        Event::NonZeroDigit => self.state = State::Integer(IntegerState::Decimal),
+                        Event::Zero => self.state = State::Integer(IntegerState::Zero),
                         Event::Dot => self.state = State::Float(FloatState::DotStart),
-                        Event::Plus |
-                        Event::Minus => self.state = State::Operator(OperatorState::PlusMinus),
+                        Event::Plus => self.state = State::Operator(OperatorState::Plus),
+                        Event::Minus => self.state = State::Operator(OperatorState::Minus),
                         Event::Asterisk => self.state = State::Operator(OperatorState::Asterisk),
+                        Event::Slash => self.state = State::Operator(OperatorState::Slash),
+                        Event::Percent => self.state = State::Operator(OperatorState::Percent),
+                        Event::At => self.state = State::Operator(OperatorState::At),
+                        Event::Ampersand => self.state = State::Operator(OperatorState::Ampersand),
+                        Event::VerticalBar => self.state = State::Operator(OperatorState::VerticalBar),
+                        Event::Caret => self.state = State::Operator(OperatorState::Caret),
                         Event::Less => self.state = State::Operator(OperatorState::Less),
-                        Event::Slash |
-                        Event::Greater => {
-                            self.state = State::Operator(OperatorState::Asterisk);
+                        Event::Greater => self.state = State::Operator(OperatorState::Greater),
+                        Event::Tilde => {
+                            self.state = State::Operator(OperatorState::Complete);
                             break;
                         }
                         Event::OpeningParenthesis |
@@ -217,289 +831,929 @@ impl Iterator for LexicalAnalyzer<'_> {
                         Event::OpeningCurlyBracket |
                         Event::ClosingCurlyBracket |
                         Event::Coma |
-                        Event::Colon => {
-                            self.state = State::Delimiter(DelimiterState::General);
+                        Event::Semicolon => {
+                            self.state = State::Delimiter(DelimiterState::Complete);
                             break;
                         }
                         Event::Equal => self.state = State::Delimiter(DelimiterState::Equal),
-                        _ => self.double_buffer.reject()
+                        Event::Colon => self.state = State::Delimiter(DelimiterState::Colon),
+                        Event::Exclamation => self.state = State::Delimiter(DelimiterState::Exclamation),
+                        Event::Space | Event::Tab if self.trivia => self.state = State::Trivia(TriviaState::Whitespace),
+                        Event::Space | Event::Tab => self.input.reject(),
+                        _ => return Some(Err(self.error(|span, text| LexError::IllegalCharacter { span, text }))),
                     }
                 }
 
                 State::Id => {
                     match self.event {
-                        Event::NonZeroDigit | Event::Zero |
+                        Event::NonZeroDigit | Event::Zero | Event::IdContinue |
                         Event::Letter | Event::Exponent | Event::X |
                         Event::Underscore => (),
                         _ => break
                     }
                 }
 
-                State::Comment(ref state) => {
-                    match state {
-                        CommentState::Q0 => {
-                            match self.event {
-                                Event::Hashtag | Event::NewLine =>
-                                    return Some(Err(
-                                        format!("Illegal character after # on line {}", self.line_counter)
-                                    )),
-                                _ => {
-                                    self.state = State::Comment(CommentState::Q1);
-                                    self.double_buffer.reject();
-                                }
+                State::Prefix(ref state) => {
+                    match (state, &self.event) {
+                        (_, Event::SingleQuote) | (_, Event::DoubleQuote) => {
+                            let quote = match self.event {
+                                Event::SingleQuote => Quote::Single,
+                                _ => Quote::Double,
+                            };
+
+                            self.prefix = state.string_prefix();
+                            self.state = if self.prefix.is_formatted() {
+                                State::FString(FStringState::Q0(quote))
+                            } else {
+                                State::String(StringState::Q0(quote))
+                            };
+                        }
+                        (PrefixState::R, Event::Letter) if (c == 'b') | (c == 'B') =>
+                            self.state = State::Prefix(PrefixState::RawBytes),
+                        (PrefixState::B, Event::Letter) if (c == 'r') | (c == 'R') =>
+                            self.state = State::Prefix(PrefixState::RawBytes),
+                        (PrefixState::R, Event::Letter) if (c == 'f') | (c == 'F') =>
+                            self.state = State::Prefix(PrefixState::RawFormatted),
+                        (PrefixState::F, Event::Letter) if (c == 'r') | (c == 'R') =>
+                            self.state = State::Prefix(PrefixState::RawFormatted),
+                        (_, Event::NonZeroDigit) | (_, Event::Zero) | (_, Event::IdContinue) |
+                        (_, Event::Letter) | (_, Event::Exponent) | (_, Event::X) |
+                        (_, Event::Underscore) => self.state = State::Id,
+                        _ => break
+                    }
+                }
+
+                State::Indent => {
+                    match self.event {
+                        Event::Space | Event::Tab => (),
+                        // Lines without code aren't indented, their whitespace is trivia
+                        Event::NewLine | Event::Hashtag if self.trivia & self.indented() => {
+                            self.state = State::Trivia(TriviaState::Whitespace);
+                            break;
+                        }
+                        Event::NewLine if self.trivia => {
+                            self.state = State::Trivia(TriviaState::BlankLine);
+                            break;
+                        }
+                        Event::NewLine => self.input.reject(),
+                        Event::Hashtag => {
+                            self.state = State::Comment;
+                            if !self.trivia {
+                                self.input.reject();
                             }
                         }
-                        CommentState::Q1 => {
-                            match self.event {
-                                Event::Hashtag =>
-                                    return Some(Err(
-                                        format!("Illegal character after # on line {}", self.line_counter)
-                                    )),
-                                Event::NewLine => {
-                                    self.state = State::Init;
-                                    self.double_buffer.reject();
-                                }
-                                _ => self.double_buffer.reject()
+                        _ => {
+                            self.input.back();
+                            if let Some(token) = self.indentation() {
+                                return Some(token);
+                            }
+                        }
+                    }
+                }
+
+                State::Comment => {
+                    match self.event {
+                        // The newline is read again after the comment
+                        Event::NewLine if self.trivia => {
+                            self.input.back();
+                            break;
+                        }
+                        _ if self.trivia => (),
+                        Event::NewLine if self.implicit_line_joining() => {
+                            self.state = State::Init;
+                            self.input.reject();
+                        }
+                        Event::NewLine if !self.modes.is_empty() => {
+                            self.input.back();
+                            return Some(Err(self.unterminated_fstring()));
+                        }
+                        Event::NewLine => {
+                            if self.logical_line {
+                                self.state = State::NewLine;
+                                break;
                             }
+                            self.state = State::Indent;
+                            self.input.reject();
+                        }
+                        _ => self.input.reject()
+                    }
+                }
+
+                // A backslash joins the next line to the current logical line
+                State::Continuation => {
+                    match self.event {
+                        Event::NewLine if self.trivia => {
+                            self.state = State::Trivia(TriviaState::Continuation);
+                            break;
                         }
+                        Event::NewLine => {
+                            self.state = State::Init;
+                            self.input.reject();
+                        }
+                        _ => return Some(Err(self.error_before(|span, text| LexError::UnexpectedCharacterAfterContinuation { span, text }))),
+                    }
+                }
+
+                State::Trivia(TriviaState::Whitespace) => {
+                    match self.event {
+                        Event::Space | Event::Tab => (),
+                        _ => break
                     }
                 }
 
+                State::NewLine | State::End | State::Imaginary | State::Trivia(_) => break,
+
                 State::String(ref state) => {
+                    // Whether the event is the quote that opened the string
+                    let closing = |quote: &Quote, event: &Event| matches!(
+                        (quote, event),
+                        (Quote::Single, Event::SingleQuote) | (Quote::Double, Event::DoubleQuote)
+                    );
+
                     match state {
-                        StringState::SingleQuoteQ0 => {
-                            match self.event {
-                                Event::SingleQuote => return Some(Err(
-                                    format!("Illegal character after ' on line {}", self.line_counter)
-                                )),
-                                _ => self.state = State::String(StringState::SingleQuoteQ1)
+                        StringState::Q0(quote) => {
+                            if closing(quote, &self.event) {
+                                self.state = State::String(StringState::Empty(*quote));
+                            } else {
+                                match self.event {
+                                    Event::NewLine => return Some(Err(self.error_before(|span, text| LexError::UnterminatedString { span, text }))),
+                                    Event::Backslash => self.state = State::String(StringState::ShortEscape(*quote)),
+                                    _ => self.state = State::String(StringState::Short(*quote)),
+                                }
                             }
                         }
-                        StringState::SingleQuoteQ1 => {
-                            match self.event {
-                                Event::SingleQuote => break,
-                                _ => ()
+                        StringState::Empty(quote) => {
+                            if closing(quote, &self.event) {
+                                self.state = State::String(StringState::Long(*quote));
+                            } else {
+                                break;
                             }
                         }
-                        StringState::DoubleQuoteQ0 => {
-                            match self.event {
-                                Event::DoubleQuote => self.state = State::String(StringState::DoubleQuoteQ2),
-                                _ => self.state = State::String(StringState::DoubleQuoteQ1),
+                        StringState::Short(quote) => {
+                            if closing(quote, &self.event) {
+                                self.state = State::String(StringState::Complete);
+                                break;
                             }
-                        }
-                        StringState::DoubleQuoteQ1 => {
                             match self.event {
-                                Event::DoubleQuote => break,
+                                Event::NewLine => return Some(Err(self.error_before(|span, text| LexError::UnterminatedString { span, text }))),
+                                Event::Backslash => self.state = State::String(StringState::ShortEscape(*quote)),
                                 _ => (),
                             }
                         }
-
-                        StringState::DoubleQuoteQ2 => {
-                            match self.event {
-                                Event::DoubleQuote => self.state = State::MultilineComment(MultilineCommentState::Q0),
-                                _ => return Some(Err(
-                                    format!("Expected another double quote on line {}", self.line_counter)
-                                )),
+                        StringState::ShortEscape(quote) => self.state = State::String(StringState::Short(*quote)),
+                        StringState::Long(quote) => {
+                            if closing(quote, &self.event) {
+                                self.state = State::String(StringState::LongQ1(*quote));
+                            } else if let Event::Backslash = self.event {
+                                self.state = State::String(StringState::LongEscape(*quote));
                             }
                         }
+                        StringState::LongQ1(quote) | StringState::LongQ2(quote) => {
+                            if closing(quote, &self.event) {
+                                if let StringState::LongQ2(_) = state {
+                                    self.state = State::String(StringState::Complete);
+                                    break;
+                                }
+                                self.state = State::String(StringState::LongQ2(*quote));
+                            } else if let Event::Backslash = self.event {
+                                self.state = State::String(StringState::LongEscape(*quote));
+                            } else {
+                                self.state = State::String(StringState::Long(*quote));
+                            }
+                        }
+                        StringState::LongEscape(quote) => self.state = State::String(StringState::Long(*quote)),
+                        StringState::Complete => break,
                     }
                 }
 
-                State::MultilineComment(ref state) => {
+                State::FString(ref state) => {
+                    let (quote, triple, raw) = self.fstring().unwrap_or((Quote::Double, false, false));
+                    let format_spec = matches!(self.modes.last(), Some(Mode::FormatSpec));
+                    let closing = matches!(
+                        (&quote, &self.event),
+                        (Quote::Single, Event::SingleQuote) | (Quote::Double, Event::DoubleQuote)
+                    );
+                    let opening = |event: &Event, quote: &Quote| matches!(
+                        (quote, event),
+                        (Quote::Single, Event::SingleQuote) | (Quote::Double, Event::DoubleQuote)
+                    );
+
                     match state {
-                        MultilineCommentState::Q0 => {
-                            match self.event {
-                                Event::DoubleQuote => self.state = State::MultilineComment(MultilineCommentState::Q1),
-                                _ => self.double_buffer.reject()
+                        FStringState::Q0(quote) => {
+                            if opening(&self.event, quote) {
+                                self.state = State::FString(FStringState::Empty(*quote));
+                            } else {
+                                self.input.back();
+                                break;
                             }
                         }
-                        MultilineCommentState::Q1 => {
-                            match self.event {
-                                Event::DoubleQuote => self.state = State::MultilineComment(MultilineCommentState::Q2),
-                                _ => return Some(Err(
-                                    format!("Expected another double quote on line {}", self.line_counter),
-                                )),
+                        FStringState::Empty(quote) => {
+                            if opening(&self.event, quote) {
+                                self.state = State::FString(FStringState::Opened(*quote));
+                                break;
                             }
+
+                            // An empty f-string, give back the second quote so it is read as the end
+                            self.input.back();
+                            self.input.back();
+                            self.state = State::FString(FStringState::Q0(*quote));
+                            break;
                         }
-                        MultilineCommentState::Q2 => {
+                        FStringState::Start => {
                             match self.event {
-                                Event::DoubleQuote => {
-                                    self.state = State::Init;
-                                    self.double_buffer.reject();
+                                Event::OpeningCurlyBracket => self.state = State::FString(FStringState::LBrace),
+                                Event::ClosingCurlyBracket if format_spec => {
+                                    self.state = State::FString(FStringState::SpecEnd);
+                                    break;
+                                }
+                                Event::ClosingCurlyBracket => self.state = State::FString(FStringState::RBrace),
+                                _ if closing && format_spec => return Some(Err(self.error(|span, text| LexError::FStringExpectingBrace { span, text }))),
+                                _ if closing && triple => self.state = State::FString(FStringState::Quote1),
+                                _ if closing => {
+                                    self.state = State::FString(FStringState::End);
+                                    break;
                                 }
-                                _ => return Some(Err(
-                                    format!("Expected another double quote on line {}", self.line_counter),
-                                )),
+                                Event::Backslash => self.state = State::FString(FStringState::Escape),
+                                Event::NewLine if !triple => return Some(Err(self.error_before(|span, text| LexError::UnterminatedFString { span, text }))),
+                                _ => self.state = State::FString(FStringState::Middle),
                             }
                         }
-                    }
-                }
-
-                State::Integer(ref state) => {
-                    match state {
-                        IntegerState::Decinteger => {
+                        FStringState::Middle => {
                             match self.event {
-                                Event::NonZeroDigit | Event::Zero => (),
-                                Event::Dot => self.state = State::Float(FloatState::Q0),
-                                Event::Exponent => self.state = State::Float(FloatState::Exponent),
-                                _ => break,
+                                Event::OpeningCurlyBracket | Event::ClosingCurlyBracket => {
+                                    self.input.back();
+                                    break;
+                                }
+                                _ if closing => {
+                                    self.input.back();
+                                    break;
+                                }
+                                Event::Backslash => self.state = State::FString(FStringState::Escape),
+                                Event::NewLine if !triple => return Some(Err(self.error_before(|span, text| LexError::UnterminatedFString { span, text }))),
+                                _ => (),
                             }
                         }
-                        IntegerState::HexintegerQ0 => {
+                        FStringState::Escape => {
                             match self.event {
-                                Event::X => self.state = State::Integer(IntegerState::HexintegerQ1),
-                                Event::Dot => self.state = State::Float(FloatState::Q0),
-                                Event::Exponent => self.state = State::Float(FloatState::Exponent),
-                                _ => break
+                                // A brace after a backslash still opens or closes a replacement field
+                                Event::OpeningCurlyBracket | Event::ClosingCurlyBracket => {
+                                    self.input.back();
+                                    self.state = State::FString(FStringState::Middle);
+                                }
+                                _ if (c == 'N') & !raw => self.state = State::FString(FStringState::NamedEscape),
+                                _ => self.state = State::FString(FStringState::Middle),
                             }
                         }
-                        IntegerState::HexintegerQ1 => {
+                        FStringState::NamedEscape => {
+                            // The braces of \N{...} belong to the escape sequence
                             match self.event {
-                                Event::NonZeroDigit | Event::Zero => (),
-                                _ => break
+                                Event::ClosingCurlyBracket => self.state = State::FString(FStringState::Middle),
+                                Event::OpeningCurlyBracket => (),
+                                _ if closing => {
+                                    self.input.back();
+                                    self.state = State::FString(FStringState::Middle);
+                                }
+                                _ => (),
                             }
                         }
-                    }
-                }
-
-                State::Float(ref state) => {
-                    match state {
-                        FloatState::Q0 => {
+                        FStringState::LBrace => {
                             match self.event {
-                                Event::NonZeroDigit |
-                                Event::Zero => self.state = State::Float(FloatState::Q1),
-                                Event::NewLine |
-                                Event::Space => break,
-                                _ => return Some(Err(
-                                    format!("Expected Float or Hexinteger after dot on line {}", self.line_counter),
-                                )),
+                                Event::OpeningCurlyBracket => self.state = State::FString(FStringState::Middle),
+                                _ => {
+                                    self.input.back();
+                                    self.state = State::FString(FStringState::Field);
+                                    break;
+                                }
                             }
                         }
-                        FloatState::Q1 => {
+                        FStringState::RBrace => {
                             match self.event {
-                                Event::NonZeroDigit | Event::Zero => (),
-                                _ => break,
+                                Event::ClosingCurlyBracket => self.state = State::FString(FStringState::Middle),
+                                _ => return Some(Err(self.error_before(|span, text| LexError::FStringSingleBrace { span, text }))),
                             }
                         }
-                        FloatState::DotStart => {
+                        FStringState::Quote1 | FStringState::Quote2 => {
+                            if !closing {
+                                // Fewer quotes than needed to close, they are part of the text
+                                self.input.back();
+                                self.state = State::FString(FStringState::Middle);
+                            } else if let FStringState::Quote1 = state {
+                                self.state = State::FString(FStringState::Quote2);
+                            } else {
+                                self.state = State::FString(FStringState::End);
+                                break;
+                            }
+                        }
+                        FStringState::Opened(_) |
+                        FStringState::Field |
+                        FStringState::SpecEnd |
+                        FStringState::End => break,
+                    }
+                }
+
+                State::Integer(state) => {
+                    let imaginary = (c == 'j') | (c == 'J');
+                    let letter = matches!(self.event, Event::Letter | Event::Exponent | Event::X);
+                    let keyword = letter && self.keyword_after_number(c);
+
+                    match state {
+                        IntegerState::Decimal | IntegerState::Zero | IntegerState::LeadingZero => {
                             match self.event {
-                                Event::NonZeroDigit | Event::Zero => self.state = State::Float(FloatState::Q1),
+                                Event::Zero => (),
+                                Event::NonZeroDigit => {
+                                    if let IntegerState::Zero = state {
+                                        self.state = State::Integer(IntegerState::LeadingZero);
+                                    }
+                                }
+                                Event::Underscore => {
+                                    self.state = State::Integer(match state {
+                                        IntegerState::Decimal => IntegerState::DecimalUnderscore,
+                                        IntegerState::Zero => IntegerState::ZeroUnderscore,
+                                        _ => IntegerState::LeadingZeroUnderscore,
+                                    });
+                                }
+                                Event::Dot => self.state = State::Float(FloatState::Point),
+                                _ if imaginary => {
+                                    self.state = State::Imaginary;
+                                    break;
+                                }
+                                Event::X if matches!(state, IntegerState::Zero) =>
+                                    self.state = State::Integer(IntegerState::RadixPrefix(Radix::Hexadecimal)),
+                                Event::Letter if matches!(state, IntegerState::Zero) & ((c == 'o') | (c == 'O')) =>
+                                    self.state = State::Integer(IntegerState::RadixPrefix(Radix::Octal)),
+                                Event::Letter if matches!(state, IntegerState::Zero) & ((c == 'b') | (c == 'B')) =>
+                                    self.state = State::Integer(IntegerState::RadixPrefix(Radix::Binary)),
+                                _ if keyword => {
+                                    if let Some(err) = self.number_before_keyword("decimal") {
+                                        return Some(Err(err));
+                                    }
+                                    break;
+                                }
+                                Event::Exponent => self.state = State::Float(FloatState::Exponent),
+                                _ if letter => return Some(Err(self.error(|span, text| LexError::InvalidNumber { radix: "decimal", span, text }))),
                                 _ => {
-                                    self.state = State::Delimiter(DelimiterState::Equal);
+                                    if let Some(err) = self.number_error_before() {
+                                        return Some(Err(err));
+                                    }
                                     break;
                                 }
                             }
                         }
-                        FloatState::Exponent => {
+                        IntegerState::DecimalUnderscore |
+                        IntegerState::ZeroUnderscore |
+                        IntegerState::LeadingZeroUnderscore => {
                             match self.event {
-                                Event::Plus |
-                                Event::Minus |
-                                Event::NonZeroDigit |
-                                Event::Zero => self.state = State::Float(FloatState::Q1),
-                                _ => return Some(Err(
-                                    format!("Expected +, - or number after Exponent on line {}", self.line_counter),
-                                )),
+                                Event::Zero => {
+                                    self.state = State::Integer(match state {
+                                        IntegerState::DecimalUnderscore => IntegerState::Decimal,
+                                        IntegerState::ZeroUnderscore => IntegerState::Zero,
+                                        _ => IntegerState::LeadingZero,
+                                    });
+                                }
+                                Event::NonZeroDigit => {
+                                    self.state = State::Integer(match state {
+                                        IntegerState::DecimalUnderscore => IntegerState::Decimal,
+                                        _ => IntegerState::LeadingZero,
+                                    });
+                                }
+                                _ => return Some(Err(self.number_error_before().unwrap())),
+                            }
+                        }
+                        IntegerState::RadixPrefix(radix) |
+                        IntegerState::RadixDigits(radix) |
+                        IntegerState::RadixUnderscore(radix) => {
+                            if c.is_digit(radix.base()) {
+                                self.state = State::Integer(IntegerState::RadixDigits(radix));
+                            } else if c.is_ascii_digit() {
+                                return Some(Err(self.error(|span, text| LexError::InvalidDigit { digit: c, radix: radix.name(), span, text })));
+                            } else if let (IntegerState::RadixDigits(_), Event::Underscore) |
+                                          (IntegerState::RadixPrefix(_), Event::Underscore) = (state, &self.event) {
+                                self.state = State::Integer(IntegerState::RadixUnderscore(radix));
+                            } else if keyword {
+                                if let Some(err) = self.number_before_keyword(radix.name()) {
+                                    return Some(Err(err));
+                                }
+                                break;
+                            } else if letter | matches!(self.event, Event::Underscore) {
+                                return Some(Err(self.error(|span, text| LexError::InvalidNumber { radix: radix.name(), span, text })));
+                            } else {
+                                if let Some(err) = self.number_error_before() {
+                                    return Some(Err(err));
+                                }
+                                break;
                             }
                         }
                     }
                 }
 
-                State::Operator(ref state) => {
+                State::Float(state) => {
+                    let imaginary = (c == 'j') | (c == 'J');
+                    let letter = matches!(self.event, Event::Letter | Event::Exponent | Event::X);
+                    let keyword = letter && self.keyword_after_number(c);
+
                     match state {
-                        OperatorState::PlusMinus => {
+                        FloatState::DotStart => {
                             match self.event {
-                                Event::Equal => {
-                                    self.state = State::Delimiter(DelimiterState::General);
+                                Event::NonZeroDigit | Event::Zero => self.state = State::Float(FloatState::Fraction),
+                                // Two dots aren't a delimiter, the second one is only read when a third follows
+                                Event::Dot if matches!(self.input.peek(0), Some(Ok('.'))) => {
+                                    self.input.next();
+                                    self.state = State::Delimiter(DelimiterState::Complete);
                                     break;
                                 }
                                 _ => break,
                             }
                         }
-                        OperatorState::Asterisk => {
+                        FloatState::Point | FloatState::Fraction | FloatState::ExponentDigits => {
                             match self.event {
-                                Event::Asterisk => break,
-                                _ => {
-                                    self.state = State::Operator(OperatorState::PlusMinus);
+                                Event::NonZeroDigit | Event::Zero => {
+                                    if let FloatState::Point = state {
+                                        self.state = State::Float(FloatState::Fraction);
+                                    }
+                                }
+                                Event::Underscore => {
+                                    match state {
+                                        FloatState::Fraction => self.state = State::Float(FloatState::FractionUnderscore),
+                                        FloatState::ExponentDigits => self.state = State::Float(FloatState::ExponentUnderscore),
+                                        _ => return Some(Err(self.error(|span, text| LexError::InvalidNumber { radix: "decimal", span, text }))),
+                                    }
+                                }
+                                _ if keyword => {
+                                    if let Some(err) = self.number_before_keyword("decimal") {
+                                        return Some(Err(err));
+                                    }
                                     break;
                                 }
+                                Event::Exponent if !matches!(state, FloatState::ExponentDigits) =>
+                                    self.state = State::Float(FloatState::Exponent),
+                                _ if imaginary => {
+                                    self.state = State::Imaginary;
+                                    break;
+                                }
+                                _ if letter => return Some(Err(self.error(|span, text| LexError::InvalidNumber { radix: "decimal", span, text }))),
+                                _ => break,
                             }
                         }
-                        OperatorState::Less => {
+                        FloatState::Exponent => {
                             match self.event {
-                                Event::Equal => break,
-                                _ => {
-                                    self.state = State::Operator(OperatorState::Asterisk);
-                                    break;
+                                Event::Plus | Event::Minus => self.state = State::Float(FloatState::ExponentSign),
+                                Event::NonZeroDigit | Event::Zero => self.state = State::Float(FloatState::ExponentDigits),
+                                _ => return Some(Err(self.number_error_before().unwrap())),
+                            }
+                        }
+                        FloatState::ExponentSign | FloatState::FractionUnderscore | FloatState::ExponentUnderscore => {
+                            match self.event {
+                                Event::NonZeroDigit | Event::Zero => {
+                                    self.state = State::Float(match state {
+                                        FloatState::FractionUnderscore => FloatState::Fraction,
+                                        _ => FloatState::ExponentDigits,
+                                    });
                                 }
+                                _ => return Some(Err(self.number_error_before().unwrap())),
                             }
                         }
                     }
                 }
 
+                /*
+                 * Operators and delimiters are read with maximal munch. Waiting states break when
+                 * the character doesn't extend the lexeme so it is given back, while the Complete
+                 * states break after taking the last character of the lexeme.
+                 */
+                State::Operator(ref state) => {
+                    match (state, &self.event) {
+                        (OperatorState::Asterisk, Event::Asterisk) =>
+                            self.state = State::Operator(OperatorState::DoubleAsterisk),
+                        (OperatorState::Slash, Event::Slash) =>
+                            self.state = State::Operator(OperatorState::DoubleSlash),
+                        (OperatorState::Less, Event::Less) =>
+                            self.state = State::Operator(OperatorState::DoubleLess),
+                        (OperatorState::Greater, Event::Greater) =>
+                            self.state = State::Operator(OperatorState::DoubleGreater),
+                        (OperatorState::Less, Event::Equal) |
+                        (OperatorState::Greater, Event::Equal) => {
+                            self.state = State::Operator(OperatorState::Complete);
+                            break;
+                        }
+                        (OperatorState::Minus, Event::Greater) |
+                        (_, Event::Equal) => {
+                            self.state = State::Delimiter(DelimiterState::Complete);
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+
                 State::Delimiter(ref state) => {
-                    match state {
-                        DelimiterState::General => {}
-                        DelimiterState::Equal => {
-                            match self.event {
-                                Event::Equal => {
-                                    self.state = State::Operator(OperatorState::PlusMinus);
-                                    break;
-                                }
-                                _ => break
-                            }
+                    // At the top level of a replacement field the colon always starts the format specifier
+                    let format_spec = matches!(self.modes.last(), Some(Mode::Replacement { depth: 0 }));
+
+                    match (state, &self.event) {
+                        (DelimiterState::Colon, Event::Equal) if format_spec => break,
+                        (DelimiterState::Equal, Event::Equal) |
+                        (DelimiterState::Colon, Event::Equal) |
+                        (DelimiterState::Exclamation, Event::Equal) => {
+                            self.state = State::Operator(OperatorState::Complete);
+                            break;
                         }
+                        _ => break,
                     }
                 }
             }
         }
 
-        // Back the forward pointer if needed to read lexeme
-        match self.state {
-            State::Id |
-            State::Integer(_) |
-            State::Float(_) => self.double_buffer.back(),
-            State::Operator(ref state) => {
-                match state {
-                    OperatorState::PlusMinus => self.double_buffer.back(),
-                    _ => (),
+        if self.eof {
+            // The forward pointer is already on the last character of the file
+            match self.state {
+                State::Indent if self.trivia & self.indented() => self.state = State::Trivia(TriviaState::Whitespace),
+                State::Comment if self.trivia => (),
+                State::Init | State::Indent | State::Comment => return self.end_of_file(),
+                State::Continuation => return Some(Err(self.error(|span, text| LexError::UnexpectedEofAfterContinuation { span, text }))),
+                State::String(StringState::Empty(_)) | State::String(StringState::Complete) => (),
+                State::String(StringState::Long(_)) |
+                State::String(StringState::LongQ1(_)) |
+                State::String(StringState::LongQ2(_)) |
+                State::String(StringState::LongEscape(_)) => return Some(Err(self.error(|span, text| LexError::UnterminatedTripleQuotedString { span, text }))),
+                State::String(_) => return Some(Err(self.error(|span, text| LexError::UnterminatedString { span, text }))),
+                State::FString(FStringState::Empty(quote)) => {
+                    self.input.back();
+                    self.state = State::FString(FStringState::Q0(quote));
                 }
-            }
-            State::Delimiter(ref state) => {
-                match state {
-                    DelimiterState::General => (),
-                    DelimiterState::Equal => self.double_buffer.back(),
+                State::FString(FStringState::Opened(_)) |
+                State::FString(FStringState::Field) |
+                State::FString(FStringState::SpecEnd) |
+                State::FString(FStringState::End) => (),
+                State::FString(_) => return Some(Err(self.error(|span, text| LexError::UnterminatedFString { span, text }))),
+                State::Integer(_) | State::Float(_) => {
+                    if let Some(err) = self.number_error() {
+                        return Some(Err(err));
+                    }
                 }
+                _ => ()
+            }
+        } else {
+            // Back the forward pointer if needed to read lexeme
+            match self.state {
+                State::Id |
+                State::Prefix(_) |
+                State::Integer(_) |
+                State::Float(_) |
+                State::Trivia(TriviaState::Whitespace) |
+                State::String(StringState::Empty(_)) => self.input.back(),
+                State::Operator(OperatorState::Complete) |
+                State::Delimiter(DelimiterState::Complete) => (),
+                State::Operator(_) |
+                State::Delimiter(_) => self.input.back(),
+                _ => ()
             }
-
-            _ => ()
         }
 
         // Read lexeme
-        let lexeme = self.double_buffer.get_lexeme();
+        let start = self.input.position();
+        let lexeme = self.input.get_lexeme();
+        let span = Span::new(start, self.input.position());
 
         // Return token
-        let token = match self.state {
-            State::Id => {
-                match self.symbol_table.get(&lexeme) {
+        let kind = match self.state {
+            State::Id | State::Prefix(_) => {
+                // Identifiers are compared in NFKC normal form
+                let key: Cow<str> = if lexeme.is_ascii() {
+                    lexeme.clone()
+                } else {
+                    Cow::Owned(lexeme.nfkc().collect())
+                };
+
+                // The token keeps the spelling of the source, the symbol table has the normal form
+                match self.symbol_table.get(key.as_ref()) {
+                    Some(TokenKind::Keyword(_, keyword)) => Ok(TokenKind::Keyword(lexeme, *keyword)),
+                    Some(TokenKind::SoftKeyword(_, keyword)) => Ok(TokenKind::SoftKeyword(lexeme, *keyword)),
+                    Some(_) => Ok(TokenKind::Id(lexeme, key)),
                     None => {
-                        self.symbol_table.insert(lexeme.clone(), Token::Id(lexeme.clone()));
-                        let token = self.symbol_table.get(&lexeme).unwrap().clone();
+                        let name = key.to_string();
+                        self.symbol_table.insert(name.clone(), TokenKind::Id(Cow::Owned(name.clone()), Cow::Owned(name)));
+                        Ok(TokenKind::Id(lexeme, key))
+                    }
+                }
+            }
+            State::String(_) => {
+                let prefix = self.prefix;
+                self.prefix = StringPrefix::None;
 
-                        Some(Ok(token))
+                match escape::decode(&lexeme, prefix) {
+                    Ok((value, warnings)) => {
+                        for message in warnings {
+                            self.warnings.push(LexWarning::InvalidEscapeSequence { message, span, text: lexeme.to_string() });
+                        }
+                        Ok(TokenKind::String(lexeme, prefix, value))
                     }
-                    Some(token) => Some(Ok(token.clone()))
+                    Err(message) => Err(LexError::InvalidString { message, span, text: lexeme.into_owned() }),
                 }
             }
-            State::String(_) => Some(Ok(Token::String(lexeme))),
-            State::Integer(_) => Some(Ok(Token::Integer(lexeme))),
-            State::Float(_) => Some(Ok(Token::Float(lexeme))),
-            State::Operator(_) => Some(Ok(Token::Operator(lexeme))),
-            State::Delimiter(_) => Some(Ok(Token::Delimiter(lexeme))),
-            _ => Some(Err(format!("Shouldn't reach here")))
+            State::FString(FStringState::Q0(quote)) | State::FString(FStringState::Opened(quote)) => {
+                let triple = matches!(self.state, State::FString(FStringState::Opened(_)));
+                let prefix = self.prefix;
+                self.prefix = StringPrefix::None;
+
+                self.modes.push(Mode::FString { quote, triple, raw: prefix.is_raw(), span, text: lexeme.clone() });
+                Ok(TokenKind::FStringStart(lexeme, prefix))
+            }
+            State::FString(FStringState::Field) => {
+                self.modes.push(Mode::Replacement { depth: 0 });
+                Ok(TokenKind::Delimiter(lexeme))
+            }
+            State::FString(FStringState::SpecEnd) => {
+                // The closing brace ends both the format specifier and the replacement field
+                self.modes.pop();
+                self.modes.pop();
+                Ok(TokenKind::Delimiter(lexeme))
+            }
+            State::FString(FStringState::End) => {
+                self.modes.pop();
+                Ok(TokenKind::FStringEnd(lexeme))
+            }
+            State::FString(_) => Ok(TokenKind::FStringMiddle(lexeme)),
+            State::Integer(_) => Ok(TokenKind::Integer(lexeme)),
+            State::Float(FloatState::DotStart) => Ok(TokenKind::Delimiter(lexeme)),
+            State::Float(_) => Ok(TokenKind::Float(lexeme)),
+            State::Imaginary => Ok(TokenKind::Imaginary(lexeme)),
+            State::Operator(_) => Ok(TokenKind::Operator(lexeme)),
+            State::Delimiter(_) => Ok(TokenKind::Delimiter(lexeme)),
+            State::NewLine => Ok(TokenKind::NewLine(lexeme)),
+            State::Comment => Ok(TokenKind::Comment(lexeme)),
+            State::Trivia(TriviaState::Whitespace) |
+            State::Trivia(TriviaState::Continuation) => Ok(TokenKind::Whitespace(lexeme)),
+            State::Trivia(TriviaState::NL) |
+            State::Trivia(TriviaState::BlankLine) => Ok(TokenKind::NL(lexeme)),
+            _ => unreachable!("Shouldn't reach here")
         };
+        let mut token = kind.map(|kind| Token::new(kind, span));
 
-        self.state = State::Init;
+        // Brackets, colons and braces move through the replacement fields of f-strings
+        if let (State::Delimiter(_), Ok(delimiter)) = (&self.state, &token) {
+            let brackets = self.brackets(delimiter);
+            self.replacement_field(&delimiter.kind);
+            token = brackets.and(token);
+        }
+
+        // A NEWLINE ends the logical line, the next one starts by measuring its indentation
+        match self.state {
+            State::NewLine => {
+                self.logical_line = false;
+                self.state = State::Indent;
+            }
+            State::Trivia(TriviaState::BlankLine) => self.state = State::Indent,
+            // Trivia doesn't start a logical line
+            State::Comment | State::Trivia(_) => self.state = self.start_state(),
+            _ => {
+                self.logical_line = true;
+                self.state = self.start_state();
+            }
+        }
+
+        Some(token)
+    }
+}
+
+impl<'s, I: InputSystem<'s>> LexicalAnalyzer<'_, 's, I> {
+    /*
+     * Resynchronize after an error and return the text skipped as an Error token. Bad characters
+     * and numbers are skipped alone, while errors inside strings skip the rest of the line.
+     */
+    fn recover(&mut self, err: &LexError) -> Token<'s> {
+        match err {
+            // The text was already read as a lexeme
+            LexError::InconsistentDedent { span, text } |
+            LexError::InconsistentTabs { span, text, .. } |
+            LexError::InvalidString { span, text, .. } |
+            LexError::UnmatchedBracket { span, text } |
+            LexError::MismatchedBracket { span, text, .. } => {
+                return Token::new(TokenKind::Error(Cow::Owned(text.clone())), *span);
+            }
+            // The opening bracket was returned long ago, the error is at the end of the file
+            LexError::UnclosedBracket { .. } => {
+                return Token::new(TokenKind::Error(Cow::Borrowed("")), Span::empty(self.input.position()));
+            }
+            LexError::IllegalCharacter { .. } |
+            LexError::UnexpectedCharacterAfterContinuation { .. } |
+            LexError::UnexpectedEofAfterContinuation { .. } |
+            LexError::UnterminatedTripleQuotedString { .. } |
+            LexError::FStringSingleBrace { .. } => (),
+            LexError::InvalidNumber { .. } |
+            LexError::InvalidDigit { .. } |
+            LexError::LeadingZeros { .. } |
+            LexError::MissingExponent { .. } => {
+                // Give back the character that revealed the error and skip the rest of the word
+                if !self.eof {
+                    self.input.back();
+                    self.skip_while(|c| identifier(c, false));
+                }
+            }
+            _ => {
+                if !self.eof {
+                    self.skip_while(|c| c != '\n');
+                }
+                // The brackets opened inside the f-strings are dropped with them
+                if let Some(Mode::FString { span, .. }) = self.modes.first() {
+                    let start = span.start.offset;
+                    self.bracket_stack.retain(|(_, span)| span.start.offset < start);
+                }
+                self.modes.clear();
+            }
+        }
+
+        let start = self.input.position();
+        let lexeme = self.input.get_lexeme();
+        let span = Span::new(start, self.input.position());
+
+        self.prefix = StringPrefix::None;
+        self.logical_line = true;
+        self.state = self.start_state();
+
+        Token::new(TokenKind::Error(lexeme), span)
+    }
+
+    // Read the characters that match, leaving the forward pointer on the last one
+    fn skip_while(&mut self, matches: impl Fn(char) -> bool) {
+        loop {
+            match self.input.next() {
+                Some(Ok(c)) if matches(c) => (),
+                Some(Err(LexError::InvalidUtf8 { .. } | LexError::NullByte { .. })) => (),
+                Some(Err(_)) => break,
+                _ => {
+                    self.input.back();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<'s, I: InputSystem<'s>> Iterator for LexicalAnalyzer<'_, 's, I> {
+    type Item = Result<Token<'s>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_token()? {
+            Err(LexError::Io { message, span }) => {
+                // Nothing else can be read
+                self.state = State::End;
+                self.pending.clear();
+                Some(Err(LexError::Io { message, span }))
+            }
+            Err(err) if self.recovery => {
+                let token = self.recover(&err);
+                self.errors.push(err);
+                Some(Ok(token))
+            }
+            Err(err) => {
+                // The analysis stops at the first error
+                self.state = State::End;
+                self.pending.clear();
+                Some(Err(err))
+            }
+            Ok(token) => Some(Ok(token)),
+        }
+    }
+}
+
+impl<'s> LexicalAnalyzer<'_, 's, SourceBuffer<'s>> {
+    // Text of a token borrowed from the source, for as long as the source lives
+    pub fn lexeme(&self, token: &Token) -> Cow<'s, str> {
+        self.input.text(token.span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token;
+
+    // Tokens of the text read in recovery mode, with the errors and warnings found in it
+    fn analyze(text: &str) -> (Vec<String>, Vec<LexError>, Vec<LexWarning>) {
+        let mut symbol_table = token::symbol_table();
+        let mut lexical_analyzer = LexicalAnalyzer::new(SourceBuffer::from_text(text).unwrap(), &mut symbol_table);
+        lexical_analyzer.set_recovery(true);
+
+        let mut kinds = Vec::new();
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+
+        while let Some(token) = lexical_analyzer.next() {
+            kinds.push(format!("{:?}", token.unwrap().kind));
+            errors.extend(lexical_analyzer.take_errors());
+            warnings.extend(lexical_analyzer.take_warnings());
+        }
+
+        (kinds, errors, warnings)
+    }
+
+    // Kinds of the tokens, written as they are debugged
+    fn kinds(kinds: &[&str]) -> Vec<String> {
+        kinds.iter().map(|kind| kind.to_string()).collect()
+    }
+
+    #[test]
+    fn number_followed_by_keyword() {
+        let (tokens, errors, warnings) = analyze("x = 1if y else 2\n");
+        assert_eq!(tokens, kinds(&[
+            r#"Id("x", "x")"#, r#"Delimiter("=")"#, r#"Integer("1")"#, r#"Keyword("if", If)"#, r#"Id("y", "y")"#,
+            r#"Keyword("else", Else)"#, r#"Integer("2")"#, r#"NewLine("\n")"#, "EndMarker",
+        ]));
+        assert!(errors.is_empty());
+        assert!(matches!(&warnings[..], [LexWarning::InvalidNumber { radix: "decimal", text, .. }] if text == "1"));
+
+        let (tokens, errors, warnings) = analyze("0x1for\n");
+        assert_eq!(tokens, kinds(&[r#"Integer("0x1f")"#, r#"Keyword("or", Or)"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(errors.is_empty());
+        assert!(matches!(&warnings[..], [LexWarning::InvalidNumber { radix: "hexadecimal", text, .. }] if text == "0x1f"));
+
+        let (tokens, errors, warnings) = analyze("1.0or 2\n");
+        assert_eq!(tokens, kinds(&[r#"Float("1.0")"#, r#"Keyword("or", Or)"#, r#"Integer("2")"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(errors.is_empty());
+        assert!(matches!(&warnings[..], [LexWarning::InvalidNumber { radix: "decimal", text, .. }] if text == "1.0"));
+
+        // The exponent isn't read when the e starts else
+        let (tokens, _, warnings) = analyze("1else\n");
+        assert_eq!(tokens, kinds(&[r#"Integer("1")"#, r#"Keyword("else", Else)"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        let (tokens, errors, _) = analyze("x = 0123\n");
+        assert_eq!(tokens, kinds(&[r#"Id("x", "x")"#, r#"Delimiter("=")"#, r#"Error("0123")"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(matches!(&errors[..], [LexError::LeadingZeros { text, .. }] if text == "0123"));
+
+        let (tokens, errors, _) = analyze("1__2\n");
+        assert_eq!(tokens, kinds(&[r#"Error("1__2")"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(matches!(&errors[..], [LexError::InvalidNumber { radix: "decimal", text, .. }] if text == "1_"));
+
+        let (tokens, errors, _) = analyze("0x\n");
+        assert_eq!(tokens, kinds(&[r#"Error("0x")"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(matches!(&errors[..], [LexError::InvalidNumber { radix: "hexadecimal", text, .. }] if text == "0x"));
+
+        let (tokens, errors, _) = analyze("1e\n");
+        assert_eq!(tokens, kinds(&[r#"Error("1e")"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(matches!(&errors[..], [LexError::MissingExponent { text, .. }] if text == "1e"));
+
+        let (tokens, errors, _) = analyze("1_\n");
+        assert_eq!(tokens, kinds(&[r#"Error("1_")"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(matches!(&errors[..], [LexError::InvalidNumber { radix: "decimal", text, .. }] if text == "1_"));
+
+        let (tokens, errors, _) = analyze("0b2\n");
+        assert_eq!(tokens, kinds(&[r#"Error("0b2")"#, r#"NewLine("\n")"#, "EndMarker"]));
+        assert!(matches!(&errors[..], [LexError::InvalidDigit { digit: '2', radix: "binary", text, .. }] if text == "0b2"));
 
-        return token;
+        // A keyword after an invalid number doesn't make it valid
+        let (_, errors, warnings) = analyze("01if\n");
+        assert!(matches!(&errors[..], [LexError::LeadingZeros { text, .. }] if text == "01"));
+        assert!(warnings.is_empty());
+
+        let (_, errors, _) = analyze("1x\n");
+        assert!(matches!(&errors[..], [LexError::InvalidNumber { radix: "decimal", text, .. }] if text == "1x"));
+    }
+
+    #[test]
+    fn fstring_with_nested_format_spec() {
+        let (tokens, errors, _) = analyze("f'{x!r:>{w}}'\n");
+        assert_eq!(tokens, kinds(&[
+            r#"FStringStart("f'", Formatted)"#, r#"Delimiter("{")"#, r#"Id("x", "x")"#, r#"Delimiter("!")"#, r#"Id("r", "r")"#,
+            r#"Delimiter(":")"#, r#"FStringMiddle(">")"#, r#"Delimiter("{")"#, r#"Id("w", "w")"#, r#"Delimiter("}")"#,
+            r#"Delimiter("}")"#, r#"FStringEnd("'")"#, r#"NewLine("\n")"#, "EndMarker",
+        ]));
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn tabs_and_spaces() {
+        // A tab reaches column 8 like the spaces of the block, but not with every tab size
+        let (tokens, errors, _) = analyze("if x:\n        a\n\tb\n");
+        assert_eq!(tokens, kinds(&[
+            r#"Keyword("if", If)"#, r#"Id("x", "x")"#, r#"Delimiter(":")"#, r#"NewLine("\n")"#, r#"Indent("        ")"#,
+            r#"Id("a", "a")"#, r#"NewLine("\n")"#, r#"Error("\t")"#, r#"Id("b", "b")"#, r#"NewLine("\n")"#, "Dedent", "EndMarker",
+        ]));
+        assert!(matches!(&errors[..], [LexError::InconsistentTabs { text, block, .. }] if (text == "\t") & (block == "        ")));
+
+        let (_, errors, _) = analyze("if x:\n\ta\n        b\n");
+        assert!(matches!(&errors[..], [LexError::InconsistentTabs { text, block, .. }] if (text == "        ") & (block == "\t")));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn mismatched_bracket() {
+        let (tokens, errors, _) = analyze("x = (1, 2]\n");
+        assert_eq!(tokens, kinds(&[
+            r#"Id("x", "x")"#, r#"Delimiter("=")"#, r#"Delimiter("(")"#, r#"Integer("1")"#, r#"Delimiter(",")"#,
+            r#"Integer("2")"#, r#"Error("]")"#, r#"NewLine("\n")"#, "EndMarker",
+        ]));
+
+        // The error points at the closing bracket and carries the span of the opening one
+        match &errors[..] {
+            [LexError::MismatchedBracket { span, text, opening, opening_span }] => {
+                assert_eq!(text, "]");
+                assert_eq!((span.start.column, span.end.column), (9, 10));
+                assert_eq!(*opening, '(');
+                assert_eq!((opening_span.start.line, opening_span.start.column, opening_span.end.column), (1, 4, 5));
+            }
+            errors => panic!("{:?}", errors),
+        }
+    }
+}
@@ -0,0 +1,10 @@
+pub mod config;
+pub mod diagnostic;
+pub mod encoding;
+pub mod error;
+pub mod escape;
+pub mod input_system;
+pub mod lexical_analyzer;
+pub mod source_buffer;
+pub mod span;
+pub mod token;
@@ -1,14 +1,11 @@
 use std::{env, process};
-use std::collections::HashMap;
-use crate::config::Config;
-use crate::input_system::DoubleBuffer;
-use crate::lexical_analyzer::LexicalAnalyzer;
-use crate::token::{Keyword, Token};
-
-mod config;
-mod input_system;
-mod lexical_analyzer;
-mod token;
+use std::io::{self, IsTerminal};
+use pythonrc::config::Config;
+use pythonrc::diagnostic::{self, Diagnostic};
+use pythonrc::encoding::BOM;
+use pythonrc::lexical_analyzer::LexicalAnalyzer;
+use pythonrc::source_buffer::SourceBuffer;
+use pythonrc::token;
 
 fn main() {
 
@@ -18,35 +15,62 @@ fn main() {
         process::exit(1);
     });
 
+    let filename = config.filename;
+
     // Create a symbol table populated with the keywords
-    let mut symbol_table = HashMap::from([
-        ("else".to_string(), Token::Keyword("else".to_string(), Keyword::Else)),
-        ("import".to_string(), Token::Keyword("import".to_string(), Keyword::Import)),
-        ("return".to_string(), Token::Keyword("return".to_string(), Keyword::Return)),
-        ("for".to_string(), Token::Keyword("for".to_string(), Keyword::For)),
-        ("as".to_string(), Token::Keyword("as".to_string(), Keyword::As)),
-        ("def".to_string(), Token::Keyword("def".to_string(), Keyword::Def)),
-        ("elif".to_string(), Token::Keyword("elif".to_string(), Keyword::Elif)),
-        ("if".to_string(), Token::Keyword("if".to_string(), Keyword::If)),
-    ]);
+    let mut symbol_table = token::symbol_table();
 
-    // Create lexical analyzer
-    let lexical_analyzer = LexicalAnalyzer::new(config, &mut symbol_table).unwrap_or_else(|err| {
-        eprint!("Problem creating the lexical analyzer: {}", err);
+    // Read the file, the tokens borrow their text from its bytes
+    let source = SourceBuffer::read(&filename).unwrap_or_else(|err| {
+        eprint!("Problem reading the source: {}", err);
+        process::exit(1);
+    });
+    let text = source.strip_prefix(&BOM).unwrap_or(&source);
+
+    // Detect the encoding of the source
+    let source_buffer = SourceBuffer::new(&source).unwrap_or_else(|err| {
+        // A bad coding declaration points at its line
+        let text = String::from_utf8_lossy(text);
+        let colour = io::stderr().is_terminal();
+
+        eprint!("{}", diagnostic::render(&Diagnostic::from(&err), &filename, &text, colour));
         process::exit(1);
     });
 
+    // Create lexical analyzer
+    let mut lexical_analyzer = LexicalAnalyzer::new(source_buffer, &mut symbol_table);
+
+    // Keep going after an error to report every problem in the file
+    lexical_analyzer.set_recovery(true);
+
+    // Show the line of source the errors point at
+    let text = lexical_analyzer.encoding().decode(text);
+    let colour = io::stderr().is_terminal();
+    let mut failed = false;
 
     // Start analysis
-    for token in lexical_analyzer {
+    while let Some(token) = lexical_analyzer.next() {
         let token = match token {
             Ok(token) => token,
             Err(err) => {
-                eprint!("Problem reading the next token: {}", err);
+                eprint!("{}", diagnostic::render(&Diagnostic::from(&err), &filename, &text, colour));
                 process::exit(1);
             }
         };
 
+        for err in lexical_analyzer.take_errors() {
+            eprint!("{}", diagnostic::render(&Diagnostic::from(&err), &filename, &text, colour));
+            failed = true;
+        }
+
+        for warning in lexical_analyzer.take_warnings() {
+            eprint!("{}", diagnostic::render(&Diagnostic::from(&warning), &filename, &text, colour));
+        }
+
         println!("{:?}", token);
     }
+
+    if failed {
+        process::exit(1);
+    }
 }
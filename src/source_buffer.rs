@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use crate::encoding::{Encoding, BOM};
+use crate::error::LexError;
+use crate::input_system::{self, ByteInput, InputSystem, Mark};
+use crate::span::{Position, Span};
+
+/*
+ * Input system over the whole source in memory, the bytes of a file read at once or a string.
+ * The lexemes are slices of the source, so the tokens borrow their text from it without copying.
+ * Only the text of a source that isn't UTF-8 is copied, when it has characters out of ASCII.
+ */
+pub struct SourceBuffer<'a> {
+    source: &'a [u8],
+    begin: usize,
+    // Index of the next byte to read, one past the end of the source after reading the end of file
+    forward: usize,
+    position: Position,
+    encoding: Encoding,
+    bom: bool,
+}
+
+impl<'a> SourceBuffer<'a> {
+    // Bytes of a file to read through a source buffer, the buffer borrows them
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<u8>, LexError> {
+        fs::read(path).map_err(|err| LexError::Io { message: err.to_string(), span: None })
+    }
+
+    pub fn from_text(text: &'a str) -> Result<SourceBuffer<'a>, LexError> {
+        SourceBuffer::new(text.as_bytes())
+    }
+
+    pub fn new(source: &'a [u8]) -> Result<SourceBuffer<'a>, LexError> {
+        let mut position = Position::new();
+
+        let (encoding, bom) = input_system::detect_encoding(source)?;
+        let begin = if bom { BOM.len() } else { 0 };
+        let forward = begin;
+        position.offset = begin;
+
+        Ok(SourceBuffer { source, begin, forward, position, encoding, bom })
+    }
+
+    // Text of the source in a span, borrowed unless it has to be decoded
+    pub fn text(&self, span: Span) -> Cow<'a, str> {
+        self.decode(&self.source[span.start.offset..span.end.offset])
+    }
+
+    fn decode(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        match self.encoding {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes),
+            // ASCII is read the same in every encoding
+            _ if bytes.is_ascii() => String::from_utf8_lossy(bytes),
+            encoding => Cow::Owned(encoding.decode(bytes)),
+        }
+    }
+
+    // Bytes read since the start of the lexeme
+    fn lexeme_bytes(&self) -> &'a [u8] {
+        &self.source[self.begin..self.forward.min(self.source.len())]
+    }
+}
+
+impl ByteInput for SourceBuffer<'_> {
+    fn next_byte(&mut self) -> Option<Result<u8, LexError>> {
+        let byte = self.source.get(self.forward).copied();
+        self.forward = (self.forward + 1).min(self.source.len() + 1);
+
+        byte.map(Ok)
+    }
+
+    fn back_byte(&mut self) {
+        self.forward -= 1;
+    }
+
+    fn last_byte(&self) -> Option<u8> {
+        self.lexeme_bytes().last().copied()
+    }
+
+    fn past_end(&self) -> bool {
+        self.forward > self.source.len()
+    }
+
+    // Error for the character of the last bytes read
+    fn invalid_byte(&self, length: usize) -> LexError {
+        input_system::undecodable(self.lexeme_bytes(), length, self.position, self.encoding)
+    }
+}
+
+impl<'a> InputSystem<'a> for SourceBuffer<'a> {
+    fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn bom(&self) -> bool {
+        self.bom
+    }
+
+    fn get_lexeme(&mut self) -> Cow<'a, str> {
+        let (lexeme, end) = self.peek_lexeme();
+        self.position = end;
+        self.begin = self.forward.min(self.source.len());
+
+        lexeme
+    }
+
+    fn peek_lexeme(&self) -> (Cow<'a, str>, Position) {
+        let bytes = self.lexeme_bytes();
+        let lexeme = self.decode(bytes);
+
+        let mut end = self.position;
+        end.advance_str(&lexeme);
+        end.offset = self.position.offset + bytes.len();
+
+        (lexeme, end)
+    }
+
+    fn reject(&mut self) {
+        self.get_lexeme();
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+
+    fn back(&mut self) {
+        input_system::back_char(self, self.encoding);
+    }
+
+    fn mark(&self) -> Mark {
+        Mark(self.forward)
+    }
+
+    fn reset(&mut self, mark: Mark) {
+        // Only the characters of the lexeme can be read again
+        assert!(mark.0 >= self.begin, "reset to a mark before the start of the lexeme");
+
+        if mark.0 < self.forward {
+            self.forward = mark.0;
+        }
+    }
+}
+
+impl Iterator for SourceBuffer<'_> {
+    type Item = Result<char, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        input_system::next_char(self, self.encoding)
+    }
+}
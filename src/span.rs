@@ -0,0 +1,67 @@
+// Columns of a tab, it moves the column to the next multiple
+pub const TAB_SIZE: usize = 8;
+
+// Location in the source, lines start at 1 and columns at 0 with tabs expanded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new() -> Position {
+        Position { offset: 0, line: 1, column: 0 }
+    }
+
+    // Move the position over the bytes of a character, a lone '\r' also ends the line
+    pub fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+
+        match c {
+            '\n' | '\r' => {
+                self.line += 1;
+                self.column = 0;
+            }
+            '\t' => self.column = (self.column / TAB_SIZE + 1) * TAB_SIZE,
+            _ => self.column += 1,
+        }
+    }
+
+    // Move the position over a text where "\r\n" is a single line break
+    pub fn advance_str(&mut self, text: &str) {
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if (c == '\r') & (chars.peek() == Some(&'\n')) {
+                self.offset += 1;
+            } else {
+                self.advance(c);
+            }
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position::new()
+    }
+}
+
+// Byte range of a token with the line and column where it starts and ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Span {
+        Span { start, end }
+    }
+
+    // Empty span for tokens without text like DEDENT
+    pub fn empty(position: Position) -> Span {
+        Span { start: position, end: position }
+    }
+}
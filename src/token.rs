@@ -1,22 +1,222 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use crate::encoding::{Encoding, BOM};
+use crate::span::Span;
+
+// A token borrows its text from the source when the input system holds it, see SourceBuffer
 #[derive(Debug, Clone)]
-pub enum Token {
-    Id(String),
-    String(String),
-    Operator(String),
-    Delimiter(String),
-    Integer(String),
-    Float(String),
-    Keyword(String, Keyword),
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub span: Span,
+}
+
+impl<'a> Token<'a> {
+    pub fn new(kind: TokenKind<'a>, span: Span) -> Token<'a> {
+        Token { kind, span }
+    }
 }
 
 #[derive(Debug, Clone)]
+pub enum TokenKind<'a> {
+    // Spelling of the source and NFKC normal form the identifier is compared in
+    Id(Cow<'a, str>, Cow<'a, str>),
+    String(Cow<'a, str>, StringPrefix, StringValue),
+    FStringStart(Cow<'a, str>, StringPrefix),
+    FStringMiddle(Cow<'a, str>),
+    FStringEnd(Cow<'a, str>),
+    Operator(Cow<'a, str>),
+    Delimiter(Cow<'a, str>),
+    Integer(Cow<'a, str>),
+    Float(Cow<'a, str>),
+    Imaginary(Cow<'a, str>),
+    Keyword(Cow<'a, str>, Keyword),
+    SoftKeyword(Cow<'a, str>, SoftKeyword),
+    NewLine(Cow<'a, str>),
+    Indent(Cow<'a, str>),
+    Dedent,
+    EndMarker,
+    // Text that couldn't be tokenized, only returned in recovery mode
+    Error(Cow<'a, str>),
+    // Trivia, only returned in trivia mode
+    Comment(Cow<'a, str>),
+    Whitespace(Cow<'a, str>),
+    NL(Cow<'a, str>),
+}
+
+impl TokenKind<'_> {
+    // Text of the source the token was read from, empty for the tokens that don't have any
+    pub fn lexeme(&self) -> &str {
+        match self {
+            TokenKind::Id(lexeme, _) |
+            TokenKind::String(lexeme, _, _) |
+            TokenKind::FStringStart(lexeme, _) |
+            TokenKind::FStringMiddle(lexeme) |
+            TokenKind::FStringEnd(lexeme) |
+            TokenKind::Operator(lexeme) |
+            TokenKind::Delimiter(lexeme) |
+            TokenKind::Integer(lexeme) |
+            TokenKind::Float(lexeme) |
+            TokenKind::Imaginary(lexeme) |
+            TokenKind::Keyword(lexeme, _) |
+            TokenKind::SoftKeyword(lexeme, _) |
+            TokenKind::NewLine(lexeme) |
+            TokenKind::Indent(lexeme) |
+            TokenKind::Error(lexeme) |
+            TokenKind::Comment(lexeme) |
+            TokenKind::Whitespace(lexeme) |
+            TokenKind::NL(lexeme) => lexeme,
+            TokenKind::Dedent |
+            TokenKind::EndMarker => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringPrefix {
+    None,
+    Raw,
+    Unicode,
+    Bytes,
+    RawBytes,
+    Formatted,
+    RawFormatted,
+}
+
+impl StringPrefix {
+    // Backslashes are not escape sequences in raw literals
+    pub fn is_raw(&self) -> bool {
+        matches!(self, StringPrefix::Raw | StringPrefix::RawBytes | StringPrefix::RawFormatted)
+    }
+
+    pub fn is_bytes(&self) -> bool {
+        matches!(self, StringPrefix::Bytes | StringPrefix::RawBytes)
+    }
+
+    pub fn is_formatted(&self) -> bool {
+        matches!(self, StringPrefix::Formatted | StringPrefix::RawFormatted)
+    }
+}
+
+// Value of a string literal once its escape sequences are decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StringValue {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Keyword {
-    Else,
-    Import,
-    Return,
-    For,
+    False,
+    None,
+    True,
+    And,
     As,
+    Assert,
+    Async,
+    Await,
+    Break,
+    Class,
+    Continue,
     Def,
+    Del,
     Elif,
+    Else,
+    Except,
+    Finally,
+    For,
+    From,
+    Global,
     If,
-}
\ No newline at end of file
+    Import,
+    In,
+    Is,
+    Lambda,
+    Nonlocal,
+    Not,
+    Or,
+    Pass,
+    Raise,
+    Return,
+    Try,
+    While,
+    With,
+    Yield,
+}
+
+// Soft keywords are only keywords in some contexts, the parser decides which one applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftKeyword {
+    Underscore,
+    Case,
+    Match,
+    Type,
+}
+
+const KEYWORDS: [(&str, Keyword); 35] = [
+    ("False", Keyword::False),
+    ("None", Keyword::None),
+    ("True", Keyword::True),
+    ("and", Keyword::And),
+    ("as", Keyword::As),
+    ("assert", Keyword::Assert),
+    ("async", Keyword::Async),
+    ("await", Keyword::Await),
+    ("break", Keyword::Break),
+    ("class", Keyword::Class),
+    ("continue", Keyword::Continue),
+    ("def", Keyword::Def),
+    ("del", Keyword::Del),
+    ("elif", Keyword::Elif),
+    ("else", Keyword::Else),
+    ("except", Keyword::Except),
+    ("finally", Keyword::Finally),
+    ("for", Keyword::For),
+    ("from", Keyword::From),
+    ("global", Keyword::Global),
+    ("if", Keyword::If),
+    ("import", Keyword::Import),
+    ("in", Keyword::In),
+    ("is", Keyword::Is),
+    ("lambda", Keyword::Lambda),
+    ("nonlocal", Keyword::Nonlocal),
+    ("not", Keyword::Not),
+    ("or", Keyword::Or),
+    ("pass", Keyword::Pass),
+    ("raise", Keyword::Raise),
+    ("return", Keyword::Return),
+    ("try", Keyword::Try),
+    ("while", Keyword::While),
+    ("with", Keyword::With),
+    ("yield", Keyword::Yield),
+];
+
+const SOFT_KEYWORDS: [(&str, SoftKeyword); 4] = [
+    ("_", SoftKeyword::Underscore),
+    ("case", SoftKeyword::Case),
+    ("match", SoftKeyword::Match),
+    ("type", SoftKeyword::Type),
+];
+
+// Create a symbol table populated with the keywords and soft keywords
+pub fn symbol_table() -> HashMap<String, TokenKind<'static>> {
+    let keywords = KEYWORDS.into_iter()
+        .map(|(lexeme, keyword)| (lexeme.to_string(), TokenKind::Keyword(Cow::Borrowed(lexeme), keyword)));
+    let soft_keywords = SOFT_KEYWORDS.into_iter()
+        .map(|(lexeme, keyword)| (lexeme.to_string(), TokenKind::SoftKeyword(Cow::Borrowed(lexeme), keyword)));
+
+    keywords.chain(soft_keywords).collect()
+}
+
+/*
+ * Rebuild the bytes of the source from its tokens. With the tokens of trivia mode, and of recovery
+ * mode when the source has errors, the result is the source read, given the encoding and BOM the
+ * lexical analyzer found. Only bytes that couldn't be decoded are not restored.
+ */
+pub fn untokenize<'a: 't, 't>(tokens: impl IntoIterator<Item = &'t Token<'a>>, encoding: Encoding, bom: bool) -> Vec<u8> {
+    let text: String = tokens.into_iter().map(|token| token.kind.lexeme()).collect();
+
+    let mut bytes = if bom { BOM.to_vec() } else { Vec::new() };
+    bytes.extend(encoding.encode(&text));
+
+    bytes
+}
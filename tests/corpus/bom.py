@@ -0,0 +1,6 @@
+﻿# -*- coding: utf-8 -*-
+# A byte order mark, identifiers in NFKC normal form and characters out of the BMP
+ﬁle = "café 🐍"
+été = ﬁle * 2
+ｉｆ = 1
+print(été, '😀')
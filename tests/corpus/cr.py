@@ -0,0 +1 @@
+# Lines ended with CR onlyclass Point:    x = 0    def move(self, dx):        self.x += dx        return [self.x,                dx]
\ No newline at end of file
@@ -0,0 +1,12 @@
+# Lines ended with CRLF
+import os, \
+    sys
+
+def walk(path):
+    """Walk a tree,
+    one directory at a time."""
+    for root, dirs, files in os.walk(path):  # comment
+        total = (len(dirs) +
+                 len(files))
+
+        yield root, total
@@ -0,0 +1,12 @@
+# Errors the recovery mode keeps going after
+x = 1 $ 2
+y = "unterminated
+z = 0x
+n = 012
+if x:
+        a = 1
+    b = 2
+c = f"{x
+d = (1, 2]
+print("done")
+e = [1,
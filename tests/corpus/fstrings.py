@@ -0,0 +1,13 @@
+name = "world"
+width = 10
+print(f"Hello, {name}!")
+print(f"{name!r:>{width}} and {{braces}}")
+print(f"{f'{name}' + f"{width:03d}"}")
+print(rf"\d+{name}\n")
+print(F"{width = }")
+message = f"""
+    {name.upper()
+     + "!"}
+    {width:>{width}.2f}
+"""
+print(f'{", ".join(str(i) for i in range(3))}')
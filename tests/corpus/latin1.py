@@ -0,0 +1,6 @@
+#!/usr/bin/env python3 ----------------------------------------------------------------------------------------------------
+# -*- coding: latin-1 -*-
+# The declaration is on the second line, after a first line longer than a buffer
+s = "caf crme brle"
+b = b"\xe9"  # rsum
+t = len(s)
@@ -0,0 +1,2 @@
+def f():
+    return 1  # no newline at the end
\ No newline at end of file
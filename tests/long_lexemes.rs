@@ -0,0 +1,34 @@
+use pythonrc::input_system::DoubleBuffer;
+use pythonrc::lexical_analyzer::LexicalAnalyzer;
+use pythonrc::token::{self, Token, TokenKind};
+
+// Tokens of the source read through a double buffer of the size the lexical analyzer runs with
+fn tokens(text: &str, trivia: bool) -> Vec<Token<'static>> {
+    let mut symbol_table = token::symbol_table();
+    let mut lexical_analyzer = LexicalAnalyzer::new(DoubleBuffer::from_text(text).unwrap(), &mut symbol_table);
+    lexical_analyzer.set_trivia(trivia);
+
+    lexical_analyzer.map(|token| token.unwrap()).collect()
+}
+
+#[test]
+fn several_megabyte_string() {
+    let string = format!("\"\"\"{}\"\"\"", "a line of a very long docstring ü\n".repeat(128 * 1024));
+    let text = format!("s = {}\nt = 1\n", string);
+
+    let tokens = tokens(&text, false);
+    assert!(string.len() > 4 * 1024 * 1024);
+    assert!(matches!(&tokens[2].kind, TokenKind::String(lexeme, _, _) if *lexeme == string));
+    assert_eq!(tokens[2].span.end.line, 128 * 1024 + 1);
+    assert!(matches!(&tokens[4].kind, TokenKind::Id(lexeme, _) if lexeme == "t"));
+}
+
+#[test]
+fn several_megabyte_comment() {
+    let comment = format!("#{}", "x".repeat(4 * 1024 * 1024));
+    let text = format!("{}\nx = 1\n", comment);
+
+    let tokens = tokens(&text, true);
+    assert!(matches!(&tokens[0].kind, TokenKind::Comment(lexeme) if *lexeme == comment));
+    assert_eq!(tokens[0].span.end.offset, comment.len());
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use pythonrc::error::LexError;
+use pythonrc::input_system::{DoubleBuffer, InputSystem};
+use pythonrc::lexical_analyzer::LexicalAnalyzer;
+use pythonrc::source_buffer::SourceBuffer;
+use pythonrc::token::{self, Token};
+
+// Files of a directory and its subdirectories, in a stable order
+fn files(dir: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    files
+}
+
+// Bytes rebuilt from the tokens of trivia and recovery mode, and the errors found on the way
+fn round_trip<'s>(input: impl InputSystem<'s>) -> (Vec<u8>, Vec<LexError>) {
+    let mut symbol_table = token::symbol_table();
+    let mut lexical_analyzer = LexicalAnalyzer::new(input, &mut symbol_table);
+    lexical_analyzer.set_trivia(true);
+    lexical_analyzer.set_recovery(true);
+
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut errors = Vec::new();
+
+    while let Some(token) = lexical_analyzer.next() {
+        tokens.push(token.unwrap());
+        errors.extend(lexical_analyzer.take_errors());
+    }
+
+    let bytes = token::untokenize(&tokens, lexical_analyzer.encoding(), lexical_analyzer.bom());
+    (bytes, errors)
+}
+
+// Both input systems give back the source, returns the errors found in it
+fn assert_round_trip(path: &Path) -> Vec<LexError> {
+    let source = fs::read(path).unwrap();
+
+    let (bytes, errors) = round_trip(DoubleBuffer::from_path(path).unwrap());
+    assert!(bytes == source, "{} isn't rebuilt from the tokens of the double buffer", path.display());
+
+    let (bytes, source_errors) = round_trip(SourceBuffer::new(&source).unwrap());
+    assert!(bytes == source, "{} isn't rebuilt from the tokens of the source buffer", path.display());
+    assert_eq!(errors, source_errors, "{}", path.display());
+
+    errors
+}
+
+#[test]
+fn round_trip_res() {
+    for path in files("res") {
+        assert_round_trip(&path);
+    }
+}
+
+#[test]
+fn round_trip_corpus() {
+    for path in files("tests/corpus") {
+        let errors = assert_round_trip(&path);
+
+        // Only the file with errors has any, the others must be read without recovering
+        let expected = path.ends_with("errors.py");
+        assert_eq!(!errors.is_empty(), expected, "{}: {:?}", path.display(), errors);
+    }
+}
+
+#[test]
+fn round_trip_text() {
+    let text = "s = f\"{x!r:>{width}}\"\r\nif s:\r\n    pass\rt = 'é' $\n";
+    let source = text.as_bytes();
+
+    let (bytes, errors) = round_trip(DoubleBuffer::from_text(text).unwrap());
+    assert_eq!(bytes, source);
+    assert_eq!(errors.len(), 1);
+
+    let (bytes, errors) = round_trip(SourceBuffer::from_text(text).unwrap());
+    assert_eq!(bytes, source);
+    assert_eq!(errors.len(), 1);
+}